@@ -130,7 +130,9 @@ fn main() -> ! {
     info!("----------------------");
     info!("");
 
-    // Read test
+    // Read test. This buffer is on the stack, in the DTCM, which the
+    // internal DMA cannot access. So `read_blocks` reads it through the
+    // FIFO. Place the buffer in the AXI SRAM to use the internal DMA
     let mut buffer = [0u8; 5120];
 
     cp.DWT.enable_cycle_counter();
@@ -0,0 +1,89 @@
+//! Example of transmitting and receiving with DMA on USART3.
+//!
+//! Received frames are terminated by an idle line, and echoed back.
+
+#![no_main]
+#![no_std]
+
+use cortex_m_rt::entry;
+#[path = "utilities/logger.rs"]
+mod logger;
+use log::info;
+
+use stm32h7xx_hal::dma::dma::{DmaConfig, StreamsTuple};
+use stm32h7xx_hal::{pac, prelude::*};
+
+// DMA1/2 cannot access DTCM, where statics are placed by default. The
+// .axisram section is not initialised at reset, so the receive buffer is
+// only written by DMA
+#[link_section = ".axisram.buffers"]
+static mut RX_BUFFER: [u8; 64] = [0; 64];
+
+#[entry]
+fn main() -> ! {
+    logger::init();
+    let dp = pac::Peripherals::take().unwrap();
+
+    // Constrain and Freeze power
+    info!("Setup PWR...                  ");
+    let pwr = dp.PWR.constrain();
+    let vos = pwr.freeze();
+
+    // Constrain and Freeze clock
+    info!("Setup RCC...                  ");
+    let rcc = dp.RCC.constrain();
+    let ccdr = rcc.sys_ck(400.mhz()).freeze(vos, &dp.SYSCFG);
+
+    // Acquire the GPIOC peripheral. This also enables the clock for
+    // GPIOC in the RCC register.
+    let gpioc = dp.GPIOC.split(ccdr.peripheral.GPIOC);
+
+    let tx = gpioc.pc10.into_alternate_af7();
+    let rx = gpioc.pc11.into_alternate_af7();
+
+    info!("");
+    info!("stm32h7xx-hal example - USART DMA");
+    info!("");
+
+    // Configure the serial peripheral.
+    let serial = dp
+        .USART3
+        .serial(
+            (tx, rx),
+            2_000_000.bps(),
+            ccdr.peripheral.USART3,
+            &ccdr.clocks,
+        )
+        .unwrap();
+
+    let (tx, mut rx) = serial.split();
+
+    let streams = StreamsTuple::new(dp.DMA1, ccdr.peripheral.DMA1);
+    let config = DmaConfig::default().memory_increment(true);
+
+    // Transmit a greeting. DMA1 reads it directly from flash
+    let tx_buffer: &'static [u8] = b"Hello, world!\n";
+    let transfer = tx.write_all_dma(streams.0, tx_buffer, config);
+    while !transfer.get_transfer_complete_flag() {}
+    let (_, mut tx, _) = transfer.free_tx();
+
+    // Receive frames that are terminated by an idle line
+    let mut rx_stream = streams.1;
+    let mut rx_buffer: &'static mut [u8] = unsafe { &mut RX_BUFFER };
+
+    loop {
+        let transfer = rx.read_dma(rx_stream, rx_buffer, config);
+        while !transfer.is_idle() && !transfer.get_transfer_complete_flag() {}
+        let (stream, receiver, buffer, length) = transfer.free_idle();
+        rx_stream = stream;
+        rx = receiver;
+        rx_buffer = buffer;
+
+        info!("Received a frame of {} bytes", length);
+
+        // Echo the frame
+        for byte in rx_buffer[..length].iter() {
+            nb::block!(tx.write(*byte)).ok();
+        }
+    }
+}
@@ -53,7 +53,7 @@ fn main() -> ! {
 
     loop {
         // Echo what is received on the serial link.
-        let received = block!(rx.read()).unwrap();
+        let received: u8 = block!(rx.read()).unwrap();
         block!(tx.write(received)).ok();
     }
 }
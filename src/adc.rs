@@ -2,15 +2,43 @@
 //!
 //! ADC1 and ADC2 share a reset line. To initialise both of them, use the
 //! [`adc12`](adc12) method.
+//!
+//! # Continuous conversion with DMA
+//!
+//! A regular [`Sequence`] of up to 16 channels can be converted
+//! continuously into a circular DMA buffer with `read_dma`. Conversions run
+//! back-to-back, or one sequence at a time on each [`ExternalTrigger`]
+//! event, typically a timer TRGO, which sets the sampling rate. The DMA
+//! stream should be configured with `circular_buffer(true)`, or used with
+//! a double buffered transfer.
+//!
+//! ```
+//! let sequence = Sequence::new().channel(&pa0).channel(&pa3);
+//! let config = DmaConfig::default()
+//!     .memory_increment(true)
+//!     .circular_buffer(true);
+//! let transfer = adc1.read_dma(
+//!     streams.0,
+//!     &sequence,
+//!     Some((ExternalTrigger::TIM6_TRGO, TriggerEdge::RISINGEDGE)),
+//!     buffer,
+//!     config,
+//! );
+//! ```
 
 use crate::hal::adc::{Channel, OneShot};
 use crate::hal::blocking::delay::DelayUs;
 
 use core::marker::PhantomData;
 
-use crate::stm32::{ADC1, ADC2, ADC3, ADC3_COMMON};
+use crate::stm32::{ADC1, ADC2, ADC3, ADC3_COMMON, DMAMUX1};
 
 use crate::delay::Delay;
+use crate::dma::{
+    dma::DMAReq,
+    traits::{MemoryAccess, Stream, TargetAddress},
+    PeripheralToMemory, StaticWriteBuffer, Transfer,
+};
 use crate::gpio::gpioa::{PA0, PA1, PA2, PA3, PA4, PA5, PA6, PA7};
 use crate::gpio::gpiob::{PB0, PB1};
 use crate::gpio::gpioc::{PC0, PC1, PC2, PC3, PC4, PC5};
@@ -31,6 +59,10 @@ const ADC_KER_CK_MAX: u32 = 36_000_000;
 const ADC_KER_CK_MAX: u32 = 100_000_000;
 
 pub type Resolution = crate::stm32::adc3::cfgr::RES_A;
+/// External trigger source for regular conversions
+pub type ExternalTrigger = crate::stm32::adc3::cfgr::EXTSEL_A;
+/// Polarity of the external trigger for regular conversions
+pub type TriggerEdge = crate::stm32::adc3::cfgr::EXTEN_A;
 trait NumberOfBits {
     fn number_of_bits(&self) -> u32;
 }
@@ -154,6 +186,61 @@ impl AdcCalLinear {
     }
 }
 
+/// Regular sequence of up to 16 channels, converted in order
+pub struct Sequence<ADC> {
+    channels: [u8; 16],
+    len: usize,
+    _adc: PhantomData<ADC>,
+}
+
+impl<ADC> Default for Sequence<ADC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ADC> Sequence<ADC> {
+    /// Creates an empty sequence
+    pub fn new() -> Self {
+        Sequence {
+            channels: [0; 16],
+            len: 0,
+            _adc: PhantomData,
+        }
+    }
+
+    /// Appends the channel of `_pin` to the sequence. A channel may appear
+    /// more than once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sequence already contains 16 conversions
+    pub fn channel<PIN>(mut self, _pin: &PIN) -> Self
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        assert!(self.len < 16, "A sequence has at most 16 conversions");
+        self.channels[self.len] = PIN::channel();
+        self.len += 1;
+        self
+    }
+
+    /// Returns the channels in the sequence
+    pub fn channels(&self) -> &[u8] {
+        &self.channels[..self.len]
+    }
+
+    /// Returns the number of conversions in the sequence
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the sequence is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
 macro_rules! adc_pins {
     ($ADC:ident, $($input:ty => $chan:expr),+ $(,)*) => {
         $(
@@ -372,7 +459,8 @@ macro_rules! adc_hal {
     ($(
         $ADC:ident: (
             $adcX: ident,
-            $Rec:ident
+            $Rec:ident,
+            $dmareq:ident
         )
     ),+ $(,)*) => {
         $(
@@ -602,6 +690,124 @@ macro_rules! adc_hal {
                     result
                 }
 
+                /// Configures continuous conversion of `sequence` with
+                /// results transferred by DMA in circular mode.
+                ///
+                /// If `trigger` is `None` the sequence is converted
+                /// repeatedly. Otherwise each trigger event converts the
+                /// whole sequence once. Conversions begin when
+                /// `start_conversion_dma` is called.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `sequence` is empty or a conversion is ongoing
+                pub fn configure_sequence_dma(
+                    &mut self,
+                    sequence: &Sequence<$ADC>,
+                    trigger: Option<(ExternalTrigger, TriggerEdge)>,
+                ) {
+                    assert!(!sequence.is_empty(), "Sequence is empty");
+                    self.check_conversion_conditions();
+
+                    // Set resolution
+                    self.rb.cfgr.modify(|_, w| unsafe { w.res().bits(self.get_resolution().into()) });
+
+                    // Set LSHIFT[3:0]
+                    self.rb.cfgr2.modify(|_, w| w.lshift().bits(self.get_lshift().value()));
+
+                    // SQ1..SQ16 are 6 bits apart, five per register, with
+                    // the sequence length L in place of SQ0
+                    let mut sqr = [(sequence.len() - 1) as u32, 0, 0, 0];
+                    let mut pcsel = 0;
+                    for (i, &chan) in sequence.channels().iter().enumerate() {
+                        self.set_chan_smp(chan);
+                        pcsel |= 1 << chan;
+                        sqr[(i + 1) / 5] |= (chan as u32) << (6 * ((i + 1) % 5));
+                    }
+
+                    // Refer to RM0433 Rev 6 - Chapters 24.4.12, 24.4.16
+                    self.rb.pcsel.write(|w| unsafe { w.pcsel().bits(pcsel) });
+                    self.rb.sqr1.write(|w| unsafe { w.bits(sqr[0]) });
+                    self.rb.sqr2.write(|w| unsafe { w.bits(sqr[1]) });
+                    self.rb.sqr3.write(|w| unsafe { w.bits(sqr[2]) });
+                    self.rb.sqr4.write(|w| unsafe { w.bits(sqr[3]) });
+
+                    // Refer to RM0433 Rev 6 - Chapters 24.4.15, 24.4.19, 24.4.27
+                    self.rb.cfgr.modify(|_, w| {
+                        w.discen().clear_bit()
+                            .ovrmod().preserve()
+                            .dmngt().dma_circular();
+                        match trigger {
+                            Some((extsel, exten)) => w
+                                .cont().single()
+                                .extsel().variant(extsel)
+                                .exten().variant(exten),
+                            None => w.cont().continuous().exten().disabled(),
+                        }
+                    });
+                    self.rb.isr.write(|w| w.ovr().clear());
+                }
+
+                /// Starts the conversions configured by
+                /// `configure_sequence_dma`. The DMA stream must already
+                /// be enabled.
+                pub fn start_conversion_dma(&mut self) {
+                    self.rb.cr.modify(|_, w| w.adstart().set_bit());
+                }
+
+                /// Stops continuous conversion and restores the one-shot
+                /// configuration
+                pub fn stop_conversion_dma(&mut self) {
+                    if self.rb.cr.read().adstart().bit_is_set() {
+                        self.stop_regular_conversion();
+                    }
+                    self.rb.pcsel.reset();
+                    self.rb.isr.write(|w| w.ovr().clear());
+
+                    // Single conversion mode, Software trigger
+                    self.rb.cfgr.modify(|_, w|
+                        w.cont().clear_bit()
+                            .exten().disabled()
+                            .discen().set_bit()
+                            .dmngt().dr()
+                    );
+                }
+
+                /// Returns true if a conversion result was lost because
+                /// the DMA controller did not read it in time. No further
+                /// conversions are transferred until the conversions are
+                /// restarted.
+                pub fn is_overrun(&self) -> bool {
+                    self.rb.isr.read().ovr().bit_is_set()
+                }
+
+                /// Configures continuous conversion of `sequence` with
+                /// `configure_sequence_dma`, and starts a DMA transfer of
+                /// the results into `buf`.
+                ///
+                /// Each result occupies one `u16` word, so LSHIFT must not
+                /// move results above bit 15.
+                pub fn read_dma<STREAM, BUF>(
+                    mut self,
+                    stream: STREAM,
+                    sequence: &Sequence<$ADC>,
+                    trigger: Option<(ExternalTrigger, TriggerEdge)>,
+                    buf: BUF,
+                    config: STREAM::Config,
+                ) -> Transfer<STREAM, Self, PeripheralToMemory, BUF>
+                where
+                    STREAM: Stream<Mux = DMAMUX1> + MemoryAccess<BUF>,
+                    BUF: StaticWriteBuffer<Word = u16>,
+                {
+                    self.configure_sequence_dma(sequence, trigger);
+
+                    let mut transfer = Transfer::<_, _, PeripheralToMemory, _>::init(
+                        stream, self, buf, config,
+                    );
+                    transfer.start(|adc| adc.start_conversion_dma());
+                    transfer
+                }
+
                 fn check_conversion_conditions(&self) {
                     // Ensure that no conversions are ongoing
                     if self.rb.cr.read().adstart().bit_is_set() {
@@ -642,6 +848,17 @@ macro_rules! adc_hal {
                 }
             }
 
+            unsafe impl TargetAddress<PeripheralToMemory> for Adc<$ADC, Enabled> {
+                type MemSize = u16;
+                type Mux = DMAMUX1;
+
+                const REQUEST_LINE: Option<u8> = Some(DMAReq::$dmareq as u8);
+
+                fn address(&self) -> usize {
+                    &self.rb.dr as *const _ as usize
+                }
+            }
+
             impl<ED> Adc<$ADC, ED> {
                 /// Save current ADC config
                 pub fn save_cfg(&mut self) -> StoredConfig {
@@ -780,7 +997,7 @@ macro_rules! adc_hal {
 }
 
 adc_hal!(
-    ADC1: (adc1, Adc12), // ADC1
-    ADC2: (adc2, Adc12), // ADC2
-    ADC3: (adc3, Adc3),  // ADC3
+    ADC1: (adc1, Adc12, ADC1_DMA), // ADC1
+    ADC2: (adc2, Adc12, ADC2_DMA), // ADC2
+    ADC3: (adc3, Adc3, ADC3_DMA),  // ADC3
 );
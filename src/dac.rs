@@ -1,13 +1,28 @@
 //! Digital to Analog Converter (DAC)
+//!
+//! # Waveform output with DMA
+//!
+//! An enabled channel can output a table of samples with `write_dma`, one
+//! sample on each event from the selected [`Trigger`]. With a DMA stream
+//! configured as a circular buffer the table is repeated indefinitely.
+//!
+//! If a trigger arrives before the DMA controller has supplied the next
+//! sample, the DMA underrun flag is set and DMA requests stop. This can be
+//! checked with `is_underrun` or signalled with `listen_underrun`.
 
 use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 
+use crate::dma::{
+    dma::DMAReq,
+    traits::{MemoryAccess, Stream, TargetAddress},
+    MemoryToPeripheral, StaticReadBuffer, Transfer,
+};
 use crate::gpio::gpioa::{PA4, PA5};
 use crate::gpio::Analog;
 use crate::hal::blocking::delay::DelayUs;
 use crate::rcc::{rec, ResetEnable};
-use crate::stm32::DAC;
+use crate::stm32::{DAC, DMAMUX1};
 use crate::traits::DacOut;
 
 /// Enabled DAC (type state)
@@ -22,6 +37,66 @@ impl ED for Enabled {}
 impl ED for EnabledUnbuffered {}
 impl ED for Disabled {}
 
+/// Type states in which the channel is enabled and can output samples
+pub trait EnabledState: ED {}
+impl EnabledState for Enabled {}
+impl EnabledState for EnabledUnbuffered {}
+
+/// DAC trigger source
+///
+/// Each trigger event moves the next sample to the output.
+//
+// Refer to RM0433 Rev 7 - Chapter 26.4.2 (Table 220)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// Timer 1 TRGO
+    TIM1_TRGO,
+    /// Timer 2 TRGO
+    TIM2_TRGO,
+    /// Timer 4 TRGO
+    TIM4_TRGO,
+    /// Timer 5 TRGO
+    TIM5_TRGO,
+    /// Timer 6 TRGO
+    TIM6_TRGO,
+    /// Timer 7 TRGO
+    TIM7_TRGO,
+    /// Timer 8 TRGO
+    TIM8_TRGO,
+    /// Timer 15 TRGO
+    TIM15_TRGO,
+    /// High resolution timer DAC trigger 1
+    HRTIM1_DACTRG1,
+    /// High resolution timer DAC trigger 2
+    HRTIM1_DACTRG2,
+    /// Low power timer 1 output
+    LPTIM1_OUT,
+    /// Low power timer 2 output
+    LPTIM2_OUT,
+    /// External interrupt line 9
+    EXTI9,
+}
+
+impl From<Trigger> for u32 {
+    fn from(val: Trigger) -> u32 {
+        match val {
+            Trigger::TIM1_TRGO => 0b0001,
+            Trigger::TIM2_TRGO => 0b0010,
+            Trigger::TIM4_TRGO => 0b0011,
+            Trigger::TIM5_TRGO => 0b0100,
+            Trigger::TIM6_TRGO => 0b0101,
+            Trigger::TIM7_TRGO => 0b0110,
+            Trigger::TIM8_TRGO => 0b0111,
+            Trigger::TIM15_TRGO => 0b1000,
+            Trigger::HRTIM1_DACTRG1 => 0b1001,
+            Trigger::HRTIM1_DACTRG2 => 0b1010,
+            Trigger::LPTIM1_OUT => 0b1011,
+            Trigger::LPTIM2_OUT => 0b1100,
+            Trigger::EXTI9 => 0b1101,
+        }
+    }
+}
+
 pub struct C1<ED> {
     _enabled: PhantomData<ED>,
 }
@@ -61,7 +136,9 @@ where
 
 macro_rules! dac {
     ($CX:ident, $en:ident, $cen:ident, $cal_flag:ident, $trim:ident,
-     $mode:ident, $dhrx:ident, $dor:ident, $daccxdhr:ident) => {
+     $mode:ident, $dhrx:ident, $dor:ident, $daccxdhr:ident,
+     $ten:ident, $tsel_shift:expr, $dmaen:ident, $dmaudr:ident,
+     $dmaudrie:ident, $dmareq:ident) => {
         impl $CX<Disabled> {
             pub fn enable(self) -> $CX<Enabled> {
                 let dac = unsafe { &(*DAC::ptr()) };
@@ -133,6 +210,100 @@ macro_rules! dac {
             }
         }
 
+        impl<ED> $CX<ED> {
+            /// Enables DMA requests, with each sample output on an event
+            /// from `trigger`. The channel is briefly disabled while the
+            /// trigger is changed.
+            pub fn enable_dma(&mut self, trigger: Trigger) {
+                let dac = unsafe { &(*DAC::ptr()) };
+                let enabled = dac.cr.read().$en().bit_is_set();
+
+                // TSEL is 4 bits wide, which is not reflected in the SVD
+                //
+                // Refer to RM0433 Rev 7 - Chapter 26.7.1
+                dac.cr.modify(|_, w| w.$en().clear_bit());
+                dac.cr.modify(|r, w| unsafe {
+                    w.bits(
+                        (r.bits() & !(0xF << $tsel_shift))
+                            | (u32::from(trigger) << $tsel_shift),
+                    )
+                });
+                dac.cr.modify(|_, w| w.$ten().set_bit().$dmaen().set_bit());
+                dac.cr.modify(|_, w| w.$en().bit(enabled));
+            }
+
+            /// Disables DMA requests and the trigger
+            pub fn disable_dma(&mut self) {
+                let dac = unsafe { &(*DAC::ptr()) };
+                dac.cr
+                    .modify(|_, w| w.$dmaen().clear_bit().$ten().clear_bit());
+            }
+
+            /// Returns true if a trigger occurred before the DMA
+            /// controller supplied the next sample. DMA requests are no
+            /// longer generated until the flag is cleared.
+            pub fn is_underrun(&self) -> bool {
+                let dac = unsafe { &(*DAC::ptr()) };
+                dac.sr.read().$dmaudr().bit_is_set()
+            }
+
+            /// Clears the DMA underrun flag
+            pub fn clear_underrun(&mut self) {
+                let dac = unsafe { &(*DAC::ptr()) };
+                dac.sr.write(|w| w.$dmaudr().set_bit());
+            }
+
+            /// Enables the DMA underrun interrupt
+            pub fn listen_underrun(&mut self) {
+                let dac = unsafe { &(*DAC::ptr()) };
+                dac.cr.modify(|_, w| w.$dmaudrie().set_bit());
+            }
+
+            /// Disables the DMA underrun interrupt
+            pub fn unlisten_underrun(&mut self) {
+                let dac = unsafe { &(*DAC::ptr()) };
+                dac.cr.modify(|_, w| w.$dmaudrie().clear_bit());
+            }
+        }
+
+        unsafe impl<ED> TargetAddress<MemoryToPeripheral> for $CX<ED> {
+            type MemSize = u16;
+            type Mux = DMAMUX1;
+
+            const REQUEST_LINE: Option<u8> = Some(DMAReq::$dmareq as u8);
+
+            fn address(&self) -> usize {
+                let dac = unsafe { &(*DAC::ptr()) };
+                &dac.$dhrx as *const _ as usize
+            }
+        }
+
+        impl<ED: EnabledState> $CX<ED> {
+            /// Outputs the 12-bit right aligned samples in `buf`, one on
+            /// each event from `trigger`. If the DMA stream is configured
+            /// with a circular buffer, the samples are output repeatedly.
+            pub fn write_dma<STREAM, BUF>(
+                mut self,
+                stream: STREAM,
+                buf: BUF,
+                trigger: Trigger,
+                config: STREAM::Config,
+            ) -> Transfer<STREAM, Self, MemoryToPeripheral, BUF>
+            where
+                STREAM: Stream<Mux = DMAMUX1> + MemoryAccess<BUF>,
+                BUF: StaticReadBuffer<Word = u16>,
+            {
+                self.clear_underrun();
+
+                let mut transfer =
+                    Transfer::<_, _, MemoryToPeripheral, _>::init(
+                        stream, self, buf, config,
+                    );
+                transfer.start(|dac| dac.enable_dma(trigger));
+                transfer
+            }
+        }
+
         /// DacOut implementation available in any Enabled/Disabled state
         impl<ED> DacOut<u16> for $CX<ED> {
             fn set_value(&mut self, val: u16) {
@@ -163,5 +334,37 @@ impl DacExt for DAC {
     }
 }
 
-dac!(C1, en1, cen1, cal_flag1, otrim1, mode1, dhr12r1, dor1, dacc1dhr);
-dac!(C2, en2, cen2, cal_flag2, otrim2, mode2, dhr12r2, dor2, dacc2dhr);
+dac!(
+    C1,
+    en1,
+    cen1,
+    cal_flag1,
+    otrim1,
+    mode1,
+    dhr12r1,
+    dor1,
+    dacc1dhr,
+    ten1,
+    2,
+    dmaen1,
+    dmaudr1,
+    dmaudrie1,
+    DAC_CH1_DMA
+);
+dac!(
+    C2,
+    en2,
+    cen2,
+    cal_flag2,
+    otrim2,
+    mode2,
+    dhr12r2,
+    dor2,
+    dacc2dhr,
+    ten2,
+    18,
+    dmaen2,
+    dmaudr2,
+    dmaudrie2,
+    DAC_CH2_DMA
+);
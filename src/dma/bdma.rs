@@ -0,0 +1,441 @@
+//! Basic DMA (BDMA)
+//!
+//! The BDMA controller sits in the D3 domain and serves the peripherals
+//! in that domain: LPUART1, SPI6, I2C4, SAI4 and ADC3. Channels 0-7 of the
+//! BDMA are connected to channels 0-7 of the DMA request multiplexer
+//! DMAMUX2.
+//!
+//! BDMA can only access SRAM4. Transfers on a BDMA channel therefore only
+//! accept buffers wrapped in [`Sram4`](struct.Sram4.html), which is
+//! checked at compile time. The [`sram4_buffer!`](../../macro.sram4_buffer.html)
+//! macro allocates such a buffer in the `.sram4` linker section.
+//!
+//! # Usage
+//!
+//! ```
+//! use stm32h7xx_hal::dma::{bdma::{BdmaConfig, ChannelsTuple}, Transfer};
+//! use stm32h7xx_hal::sram4_buffer;
+//!
+//! let channels = ChannelsTuple::new(dp.BDMA, ccdr.peripheral.BDMA);
+//!
+//! // Allocate 32 bytes in SRAM4. Returns `None` if called twice
+//! let buffer = sram4_buffer!(u8; 32).unwrap();
+//!
+//! let config = BdmaConfig::default().memory_increment(true);
+//! let mut transfer = Transfer::init(channels.0, target, buffer, config);
+//! ```
+//!
+//! The linker script must place the `.sram4` section in SRAM4, as in the
+//! `memory.x` file in this repository.
+
+use core::marker::PhantomData;
+
+use super::dma::Priority;
+use super::traits::sealed::Sealed;
+use super::traits::{MemoryAccess, Stream};
+use super::{DmaDirection, StaticReadBuffer, StaticWriteBuffer};
+use crate::rcc::{rec, ResetEnable};
+use crate::stm32::{BDMA, DMAMUX2};
+
+/// DMAMUX2 request lines
+pub type DMAReq = crate::stm32::dmamux2::ccr::DMAREQ_ID_A;
+
+/// A buffer located in SRAM4, the only memory that BDMA can access
+///
+/// Safely constructed by the [`sram4_buffer!`](../../macro.sram4_buffer.html)
+/// macro.
+pub struct Sram4<B>(B);
+
+impl<B> Sram4<B> {
+    /// Wraps a buffer located in SRAM4
+    ///
+    /// # Safety
+    ///
+    /// The memory of `buf` must be located in SRAM4
+    /// (0x3800_0000 - 0x3800_FFFF)
+    pub unsafe fn new_unchecked(buf: B) -> Self {
+        Sram4(buf)
+    }
+
+    /// Returns the wrapped buffer
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+}
+
+unsafe impl<B: StaticReadBuffer> StaticReadBuffer for Sram4<B> {
+    type Word = B::Word;
+
+    fn static_read_buffer(&self) -> (*const Self::Word, usize) {
+        self.0.static_read_buffer()
+    }
+}
+
+unsafe impl<B: StaticWriteBuffer> StaticWriteBuffer for Sram4<B> {
+    type Word = B::Word;
+
+    fn static_write_buffer(&mut self) -> (*mut Self::Word, usize) {
+        self.0.static_write_buffer()
+    }
+}
+
+/// Allocates a zeroed buffer in SRAM4 that can be used for BDMA transfers.
+///
+/// `sram4_buffer!(u16; 64)` evaluates to
+/// `Option<Sram4<&'static mut [u16]>>`. Each invocation of the macro
+/// returns `Some` the first time that it is evaluated, and `None`
+/// thereafter.
+///
+/// The buffer is placed in the `.sram4` linker section, which must be
+/// located in SRAM4 by the linker script.
+#[macro_export]
+macro_rules! sram4_buffer {
+    ($T:ty; $N:expr) => {{
+        use core::mem::MaybeUninit;
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        #[link_section = ".sram4"]
+        static mut BUFFER: MaybeUninit<[$T; $N]> = MaybeUninit::uninit();
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            // unsafe: TAKEN ensures that BUFFER is only borrowed once. The
+            // .sram4 section is not loaded, so the buffer is zeroed here
+            unsafe {
+                let ptr = BUFFER.as_mut_ptr() as *mut $T;
+                core::ptr::write_bytes(ptr, 0, $N);
+                let buf: &'static mut [$T] =
+                    core::slice::from_raw_parts_mut(ptr, $N);
+                Some($crate::dma::bdma::Sram4::new_unchecked(buf))
+            }
+        }
+    }};
+}
+
+/// Configuration for a BDMA channel.
+///
+/// This structure uses builder semantics to generate the configuration.
+///
+/// `Example`
+/// ```
+/// let config = BdmaConfig::default()
+///     .memory_increment(true)
+///     .circular_buffer(true);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct BdmaConfig {
+    pub(crate) priority: Priority,
+    pub(crate) memory_increment: bool,
+    pub(crate) peripheral_increment: bool,
+    pub(crate) circular_buffer: bool,
+    pub(crate) transfer_complete_interrupt: bool,
+    pub(crate) half_transfer_interrupt: bool,
+    pub(crate) transfer_error_interrupt: bool,
+}
+
+impl Default for BdmaConfig {
+    fn default() -> Self {
+        Self {
+            priority: Priority::Medium,
+            memory_increment: false,
+            peripheral_increment: false,
+            circular_buffer: false,
+            transfer_complete_interrupt: false,
+            half_transfer_interrupt: false,
+            transfer_error_interrupt: false,
+        }
+    }
+}
+
+impl BdmaConfig {
+    /// Set the priority of the channel
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Increment the memory address after each transfer
+    pub fn memory_increment(mut self, memory_increment: bool) -> Self {
+        self.memory_increment = memory_increment;
+        self
+    }
+    /// Increment the peripheral address after each transfer
+    pub fn peripheral_increment(mut self, peripheral_increment: bool) -> Self {
+        self.peripheral_increment = peripheral_increment;
+        self
+    }
+    /// Restart the transfer from the start of the buffer after it
+    /// completes
+    pub fn circular_buffer(mut self, circular_buffer: bool) -> Self {
+        self.circular_buffer = circular_buffer;
+        self
+    }
+    /// Enable the transfer complete interrupt
+    pub fn transfer_complete_interrupt(
+        mut self,
+        transfer_complete_interrupt: bool,
+    ) -> Self {
+        self.transfer_complete_interrupt = transfer_complete_interrupt;
+        self
+    }
+    /// Enable the half transfer interrupt
+    pub fn half_transfer_interrupt(
+        mut self,
+        half_transfer_interrupt: bool,
+    ) -> Self {
+        self.half_transfer_interrupt = half_transfer_interrupt;
+        self
+    }
+    /// Enable the transfer error interrupt
+    pub fn transfer_error_interrupt(
+        mut self,
+        transfer_error_interrupt: bool,
+    ) -> Self {
+        self.transfer_error_interrupt = transfer_error_interrupt;
+        self
+    }
+}
+
+// Flag bits within the ISR and IFCR registers, relative to the channel
+// offset
+const GIF: u32 = 1 << 0;
+const TCIF: u32 = 1 << 1;
+const HTIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const ALL_FLAGS: u32 = GIF | TCIF | HTIF | TEIF;
+
+macro_rules! bdma_channel {
+    ($($ChannelX:ident: $x:expr, $ccr:ident, $cndtr:ident, $cpar:ident, $cmar:ident;)+) => {
+        $(
+            /// BDMA Channel
+            pub struct $ChannelX {
+                _bdma: PhantomData<BDMA>,
+            }
+
+            impl Sealed for $ChannelX {}
+
+            impl $ChannelX {
+                /// Channel number within the BDMA controller
+                pub const NUMBER: usize = $x;
+
+                #[inline(always)]
+                fn bdma() -> &'static crate::stm32::bdma::RegisterBlock {
+                    // unsafe: Only the registers of this channel are
+                    // written
+                    unsafe { &*BDMA::ptr() }
+                }
+
+                #[inline(always)]
+                fn flags() -> u32 {
+                    Self::bdma().isr.read().bits() >> (4 * $x)
+                }
+
+                #[inline(always)]
+                fn clear_flags(&mut self, flags: u32) {
+                    // unsafe: Write-1-to-clear only affects the flags of
+                    // this channel
+                    Self::bdma()
+                        .ifcr
+                        .write(|w| unsafe { w.bits(flags << (4 * $x)) });
+                }
+            }
+
+            // BDMA can only access SRAM4
+            unsafe impl<B> MemoryAccess<Sram4<B>> for $ChannelX {}
+
+            impl Stream for $ChannelX {
+                type Config = BdmaConfig;
+                type Mux = DMAMUX2;
+
+                fn apply_config(&mut self, config: BdmaConfig) {
+                    Self::bdma().$ccr.modify(|_, w| unsafe {
+                        w.pl()
+                            .bits(config.priority.bits())
+                            .minc()
+                            .bit(config.memory_increment)
+                            .pinc()
+                            .bit(config.peripheral_increment)
+                            .circ()
+                            .bit(config.circular_buffer)
+                            .tcie()
+                            .bit(config.transfer_complete_interrupt)
+                            .htie()
+                            .bit(config.half_transfer_interrupt)
+                            .teie()
+                            .bit(config.transfer_error_interrupt)
+                    });
+                }
+
+                #[inline(always)]
+                fn clear_interrupts(&mut self) {
+                    self.clear_flags(ALL_FLAGS);
+                }
+
+                #[inline(always)]
+                fn clear_transfer_complete_interrupt(&mut self) {
+                    self.clear_flags(TCIF);
+                }
+
+                #[inline(always)]
+                fn clear_half_transfer_interrupt(&mut self) {
+                    self.clear_flags(HTIF);
+                }
+
+                #[inline(always)]
+                fn clear_transfer_error_interrupt(&mut self) {
+                    self.clear_flags(TEIF);
+                }
+
+                #[inline(always)]
+                fn get_transfer_complete_flag() -> bool {
+                    Self::flags() & TCIF != 0
+                }
+
+                #[inline(always)]
+                fn get_half_transfer_flag() -> bool {
+                    Self::flags() & HTIF != 0
+                }
+
+                #[inline(always)]
+                fn get_transfer_error_flag() -> bool {
+                    Self::flags() & TEIF != 0
+                }
+
+                #[inline(always)]
+                fn set_peripheral_address(&mut self, value: usize) {
+                    Self::bdma()
+                        .$cpar
+                        .write(|w| unsafe { w.pa().bits(value as u32) });
+                }
+
+                #[inline(always)]
+                fn set_memory_address(&mut self, value: usize) {
+                    Self::bdma()
+                        .$cmar
+                        .write(|w| unsafe { w.ma().bits(value as u32) });
+                }
+
+                #[inline(always)]
+                fn set_number_of_transfers(&mut self, value: u16) {
+                    Self::bdma()
+                        .$cndtr
+                        .write(|w| unsafe { w.ndt().bits(value) });
+                }
+
+                #[inline(always)]
+                fn get_number_of_transfers() -> u16 {
+                    Self::bdma().$cndtr.read().ndt().bits()
+                }
+
+                #[inline(always)]
+                unsafe fn set_memory_size(&mut self, size: u8) {
+                    Self::bdma().$ccr.modify(|_, w| w.msize().bits(size));
+                }
+
+                #[inline(always)]
+                unsafe fn set_peripheral_size(&mut self, size: u8) {
+                    Self::bdma().$ccr.modify(|_, w| w.psize().bits(size));
+                }
+
+                #[inline(always)]
+                fn set_direction(&mut self, direction: DmaDirection) {
+                    // In memory-to-memory mode the peripheral address is
+                    // the source
+                    Self::bdma().$ccr.modify(|_, w| match direction {
+                        DmaDirection::PeripheralToMemory => {
+                            w.dir().clear_bit().mem2mem().clear_bit()
+                        }
+                        DmaDirection::MemoryToPeripheral => {
+                            w.dir().set_bit().mem2mem().clear_bit()
+                        }
+                        DmaDirection::MemoryToMemory => {
+                            w.dir().clear_bit().mem2mem().set_bit()
+                        }
+                    });
+                }
+
+                #[inline(always)]
+                fn set_request_line(&mut self, request_line: u8) {
+                    // unsafe: Owned exclusive access to this DMAMUX
+                    // channel
+                    let dmamux = unsafe { &*DMAMUX2::ptr() };
+                    dmamux.ccr[$x]
+                        .modify(|_, w| unsafe { w.dmareq_id().bits(request_line) });
+                }
+
+                #[inline(always)]
+                unsafe fn enable(&mut self) {
+                    Self::bdma().$ccr.modify(|_, w| w.en().set_bit());
+                }
+
+                #[inline(always)]
+                fn is_enabled() -> bool {
+                    Self::bdma().$ccr.read().en().bit_is_set()
+                }
+
+                fn disable(&mut self) {
+                    Self::bdma().$ccr.modify(|_, w| w.en().clear_bit());
+                    while Self::is_enabled() {}
+                }
+
+                #[inline(always)]
+                fn set_transfer_complete_interrupt_enable(&mut self, enable: bool) {
+                    Self::bdma().$ccr.modify(|_, w| w.tcie().bit(enable));
+                }
+
+                #[inline(always)]
+                fn set_half_transfer_interrupt_enable(&mut self, enable: bool) {
+                    Self::bdma().$ccr.modify(|_, w| w.htie().bit(enable));
+                }
+
+                #[inline(always)]
+                fn set_transfer_error_interrupt_enable(&mut self, enable: bool) {
+                    Self::bdma().$ccr.modify(|_, w| w.teie().bit(enable));
+                }
+            }
+        )+
+    };
+}
+
+bdma_channel! {
+    Channel0: 0, ccr1, cndtr1, cpar1, cmar1;
+    Channel1: 1, ccr2, cndtr2, cpar2, cmar2;
+    Channel2: 2, ccr3, cndtr3, cpar3, cmar3;
+    Channel3: 3, ccr4, cndtr4, cpar4, cmar4;
+    Channel4: 4, ccr5, cndtr5, cpar5, cmar5;
+    Channel5: 5, ccr6, cndtr6, cpar6, cmar6;
+    Channel6: 6, ccr7, cndtr7, cpar7, cmar7;
+    Channel7: 7, ccr8, cndtr8, cpar8, cmar8;
+}
+
+/// All the channels of the BDMA controller
+pub struct ChannelsTuple(
+    pub Channel0,
+    pub Channel1,
+    pub Channel2,
+    pub Channel3,
+    pub Channel4,
+    pub Channel5,
+    pub Channel6,
+    pub Channel7,
+);
+
+impl ChannelsTuple {
+    /// Splits the BDMA peripheral into channels. The BDMA controller is
+    /// enabled and reset.
+    pub fn new(_regs: BDMA, prec: rec::Bdma) -> Self {
+        prec.enable().reset();
+
+        Self(
+            Channel0 { _bdma: PhantomData },
+            Channel1 { _bdma: PhantomData },
+            Channel2 { _bdma: PhantomData },
+            Channel3 { _bdma: PhantomData },
+            Channel4 { _bdma: PhantomData },
+            Channel5 { _bdma: PhantomData },
+            Channel6 { _bdma: PhantomData },
+            Channel7 { _bdma: PhantomData },
+        )
+    }
+}
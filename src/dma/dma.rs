@@ -0,0 +1,585 @@
+//! DMA1 and DMA2
+//!
+//! Each stream of DMA1 and DMA2 is connected to a channel of the DMA
+//! request multiplexer (DMAMUX1). Streams 0-7 of DMA1 use DMAMUX1
+//! channels 0-7, and streams 0-7 of DMA2 use DMAMUX1 channels 8-15. Since
+//! each `StreamX` owns its DMAMUX1 channel, the request line of a stream
+//! can be set without further synchronisation.
+
+use core::marker::PhantomData;
+
+use super::traits::sealed::Sealed;
+use super::traits::{DoubleBufferedStream, MemoryAccess, Stream};
+use super::{CurrentBuffer, DmaDirection};
+use crate::rcc::{rec, ResetEnable};
+use crate::stm32::dma1::{st::CR, RegisterBlock};
+use crate::stm32::{DMA1, DMA2, DMAMUX1};
+
+/// DMAMUX1 request lines
+pub type DMAReq = crate::stm32::dmamux1::ccr::DMAREQ_ID_A;
+
+/// Trait for the DMA1 and DMA2 instances
+pub trait Instance: Sealed {
+    /// Reset and Enable Control token for this instance
+    type Rec: ResetEnable;
+
+    /// Pointer to the register block
+    fn ptr() -> *const RegisterBlock;
+
+    /// Offset of the first channel of this instance in DMAMUX1
+    const DMAMUX_STREAM_OFFSET: usize;
+}
+
+impl Sealed for DMA1 {}
+impl Instance for DMA1 {
+    type Rec = rec::Dma1;
+
+    #[inline(always)]
+    fn ptr() -> *const RegisterBlock {
+        DMA1::ptr()
+    }
+
+    const DMAMUX_STREAM_OFFSET: usize = 0;
+}
+
+impl Sealed for DMA2 {}
+impl Instance for DMA2 {
+    type Rec = rec::Dma2;
+
+    #[inline(always)]
+    fn ptr() -> *const RegisterBlock {
+        DMA2::ptr()
+    }
+
+    const DMAMUX_STREAM_OFFSET: usize = 8;
+}
+
+/// Stream priority level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// Low priority
+    Low,
+    /// Medium priority
+    Medium,
+    /// High priority
+    High,
+    /// Very high priority
+    VeryHigh,
+}
+
+impl Priority {
+    pub(crate) fn bits(self) -> u8 {
+        match self {
+            Priority::Low => 0b00,
+            Priority::Medium => 0b01,
+            Priority::High => 0b10,
+            Priority::VeryHigh => 0b11,
+        }
+    }
+}
+
+/// FIFO threshold level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FifoLevel {
+    /// 1/4 full FIFO
+    QuarterFull,
+    /// 1/2 full FIFO
+    HalfFull,
+    /// 3/4 full FIFO
+    ThreeQuarterFull,
+    /// Full FIFO
+    Full,
+}
+
+impl FifoLevel {
+    fn bits(self) -> u8 {
+        match self {
+            FifoLevel::QuarterFull => 0b00,
+            FifoLevel::HalfFull => 0b01,
+            FifoLevel::ThreeQuarterFull => 0b10,
+            FifoLevel::Full => 0b11,
+        }
+    }
+}
+
+/// Burst configuration for the memory or peripheral port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BurstMode {
+    /// Single transfer
+    NoBurst,
+    /// Incremental burst of 4 beats
+    Burst4,
+    /// Incremental burst of 8 beats
+    Burst8,
+    /// Incremental burst of 16 beats
+    Burst16,
+}
+
+impl BurstMode {
+    fn bits(self) -> u8 {
+        match self {
+            BurstMode::NoBurst => 0b00,
+            BurstMode::Burst4 => 0b01,
+            BurstMode::Burst8 => 0b10,
+            BurstMode::Burst16 => 0b11,
+        }
+    }
+}
+
+/// Configuration for a DMA1 or DMA2 stream.
+///
+/// This structure uses builder semantics to generate the configuration.
+///
+/// `Example`
+/// ```
+/// let config = DmaConfig::default()
+///     .memory_increment(true)
+///     .fifo_enable(true)
+///     .fifo_threshold(FifoLevel::Full)
+///     .memory_burst(BurstMode::Burst4);
+/// ```
+///
+/// Burst transfers can only be used when the FIFO is enabled. See RM0433
+/// Rev 7 Section 15.3.11 for the allowed combinations of FIFO threshold
+/// and burst length.
+#[derive(Debug, Clone, Copy)]
+pub struct DmaConfig {
+    pub(crate) priority: Priority,
+    pub(crate) memory_increment: bool,
+    pub(crate) peripheral_increment: bool,
+    pub(crate) circular_buffer: bool,
+    pub(crate) transfer_complete_interrupt: bool,
+    pub(crate) half_transfer_interrupt: bool,
+    pub(crate) transfer_error_interrupt: bool,
+    pub(crate) direct_mode_error_interrupt: bool,
+    pub(crate) fifo_error_interrupt: bool,
+    pub(crate) fifo_enable: bool,
+    pub(crate) fifo_threshold: FifoLevel,
+    pub(crate) memory_burst: BurstMode,
+    pub(crate) peripheral_burst: BurstMode,
+}
+
+impl Default for DmaConfig {
+    fn default() -> Self {
+        Self {
+            priority: Priority::Medium,
+            memory_increment: false,
+            peripheral_increment: false,
+            circular_buffer: false,
+            transfer_complete_interrupt: false,
+            half_transfer_interrupt: false,
+            transfer_error_interrupt: false,
+            direct_mode_error_interrupt: false,
+            fifo_error_interrupt: false,
+            fifo_enable: false,
+            fifo_threshold: FifoLevel::QuarterFull,
+            memory_burst: BurstMode::NoBurst,
+            peripheral_burst: BurstMode::NoBurst,
+        }
+    }
+}
+
+impl DmaConfig {
+    /// Set the priority of the stream
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Increment the memory address after each transfer
+    pub fn memory_increment(mut self, memory_increment: bool) -> Self {
+        self.memory_increment = memory_increment;
+        self
+    }
+    /// Increment the peripheral address after each transfer
+    pub fn peripheral_increment(mut self, peripheral_increment: bool) -> Self {
+        self.peripheral_increment = peripheral_increment;
+        self
+    }
+    /// Restart the transfer from the start of the buffer after it
+    /// completes. Double buffered transfers are always circular
+    pub fn circular_buffer(mut self, circular_buffer: bool) -> Self {
+        self.circular_buffer = circular_buffer;
+        self
+    }
+    /// Enable the transfer complete interrupt
+    pub fn transfer_complete_interrupt(
+        mut self,
+        transfer_complete_interrupt: bool,
+    ) -> Self {
+        self.transfer_complete_interrupt = transfer_complete_interrupt;
+        self
+    }
+    /// Enable the half transfer interrupt
+    pub fn half_transfer_interrupt(
+        mut self,
+        half_transfer_interrupt: bool,
+    ) -> Self {
+        self.half_transfer_interrupt = half_transfer_interrupt;
+        self
+    }
+    /// Enable the transfer error interrupt
+    pub fn transfer_error_interrupt(
+        mut self,
+        transfer_error_interrupt: bool,
+    ) -> Self {
+        self.transfer_error_interrupt = transfer_error_interrupt;
+        self
+    }
+    /// Enable the direct mode error interrupt
+    pub fn direct_mode_error_interrupt(
+        mut self,
+        direct_mode_error_interrupt: bool,
+    ) -> Self {
+        self.direct_mode_error_interrupt = direct_mode_error_interrupt;
+        self
+    }
+    /// Enable the FIFO error interrupt
+    pub fn fifo_error_interrupt(mut self, fifo_error_interrupt: bool) -> Self {
+        self.fifo_error_interrupt = fifo_error_interrupt;
+        self
+    }
+    /// Enable the FIFO. Otherwise the stream operates in direct mode
+    pub fn fifo_enable(mut self, fifo_enable: bool) -> Self {
+        self.fifo_enable = fifo_enable;
+        self
+    }
+    /// Set the FIFO threshold level. Only has an effect if the FIFO is
+    /// enabled
+    pub fn fifo_threshold(mut self, fifo_threshold: FifoLevel) -> Self {
+        self.fifo_threshold = fifo_threshold;
+        self
+    }
+    /// Set the burst mode of the memory port. Only has an effect if the
+    /// FIFO is enabled
+    pub fn memory_burst(mut self, memory_burst: BurstMode) -> Self {
+        self.memory_burst = memory_burst;
+        self
+    }
+    /// Set the burst mode of the peripheral port. Only has an effect if
+    /// the FIFO is enabled
+    pub fn peripheral_burst(mut self, peripheral_burst: BurstMode) -> Self {
+        self.peripheral_burst = peripheral_burst;
+        self
+    }
+}
+
+// Flag bits within the LISR/HISR and LIFCR/HIFCR registers, relative to
+// the stream offset
+const FEIF: u32 = 1 << 0;
+const DMEIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const HTIF: u32 = 1 << 4;
+const TCIF: u32 = 1 << 5;
+const ALL_FLAGS: u32 = FEIF | DMEIF | TEIF | HTIF | TCIF;
+
+macro_rules! dma_stream {
+    ($($StreamX:ident: $x:expr, $isr:ident, $ifcr:ident, $shift:expr;)+) => {
+        $(
+            /// DMA Stream
+            pub struct $StreamX<DMA> {
+                _dma: PhantomData<DMA>,
+            }
+
+            impl<DMA: Instance> Sealed for $StreamX<DMA> {}
+
+            impl<DMA: Instance> $StreamX<DMA> {
+                /// Stream number within this DMA controller
+                pub const NUMBER: usize = $x;
+
+                #[inline(always)]
+                fn cr() -> &'static CR {
+                    // unsafe: Owned exclusive access to this stream
+                    unsafe { &(*DMA::ptr()).st[$x].cr }
+                }
+
+                #[inline(always)]
+                fn flags() -> u32 {
+                    // unsafe: Atomic read with no side effects
+                    let dma = unsafe { &*DMA::ptr() };
+                    dma.$isr.read().bits() >> $shift
+                }
+
+                #[inline(always)]
+                fn clear_flags(&mut self, flags: u32) {
+                    // unsafe: Write-1-to-clear only affects the flags of
+                    // this stream
+                    let dma = unsafe { &*DMA::ptr() };
+                    dma.$ifcr.write(|w| unsafe { w.bits(flags << $shift) });
+                }
+
+                /// Returns true if the direct mode error flag is set
+                pub fn get_direct_mode_error_flag() -> bool {
+                    Self::flags() & DMEIF != 0
+                }
+
+                /// Returns true if the FIFO error flag is set
+                pub fn get_fifo_error_flag() -> bool {
+                    Self::flags() & FEIF != 0
+                }
+
+                /// Clear direct mode error interrupt (dmeif) for the DMA
+                /// stream
+                pub fn clear_direct_mode_error_interrupt(&mut self) {
+                    self.clear_flags(DMEIF);
+                }
+
+                /// Clear FIFO error interrupt (feif) for the DMA stream
+                pub fn clear_fifo_error_interrupt(&mut self) {
+                    self.clear_flags(FEIF);
+                }
+            }
+
+            impl<DMA: Instance> Stream for $StreamX<DMA> {
+                type Config = DmaConfig;
+                type Mux = DMAMUX1;
+
+                fn apply_config(&mut self, config: DmaConfig) {
+                    Self::cr().modify(|_, w| {
+                        w.pl()
+                            .bits(config.priority.bits())
+                            .minc()
+                            .bit(config.memory_increment)
+                            .pinc()
+                            .bit(config.peripheral_increment)
+                            .circ()
+                            .bit(config.circular_buffer)
+                            .tcie()
+                            .bit(config.transfer_complete_interrupt)
+                            .htie()
+                            .bit(config.half_transfer_interrupt)
+                            .teie()
+                            .bit(config.transfer_error_interrupt)
+                            .dmeie()
+                            .bit(config.direct_mode_error_interrupt)
+                            .mburst()
+                            .bits(config.memory_burst.bits())
+                            .pburst()
+                            .bits(config.peripheral_burst.bits())
+                            .pfctrl()
+                            .dma()
+                    });
+
+                    // unsafe: Owned exclusive access to this stream
+                    let fcr = unsafe { &(*DMA::ptr()).st[$x].fcr };
+                    fcr.modify(|_, w| {
+                        w.feie()
+                            .bit(config.fifo_error_interrupt)
+                            .dmdis()
+                            .bit(config.fifo_enable)
+                            .fth()
+                            .bits(config.fifo_threshold.bits())
+                    });
+                }
+
+                #[inline(always)]
+                fn clear_interrupts(&mut self) {
+                    self.clear_flags(ALL_FLAGS);
+                }
+
+                #[inline(always)]
+                fn clear_transfer_complete_interrupt(&mut self) {
+                    self.clear_flags(TCIF);
+                }
+
+                #[inline(always)]
+                fn clear_half_transfer_interrupt(&mut self) {
+                    self.clear_flags(HTIF);
+                }
+
+                #[inline(always)]
+                fn clear_transfer_error_interrupt(&mut self) {
+                    self.clear_flags(TEIF);
+                }
+
+                #[inline(always)]
+                fn get_transfer_complete_flag() -> bool {
+                    Self::flags() & TCIF != 0
+                }
+
+                #[inline(always)]
+                fn get_half_transfer_flag() -> bool {
+                    Self::flags() & HTIF != 0
+                }
+
+                #[inline(always)]
+                fn get_transfer_error_flag() -> bool {
+                    Self::flags() & TEIF != 0
+                }
+
+                #[inline(always)]
+                fn set_peripheral_address(&mut self, value: usize) {
+                    // unsafe: Owned exclusive access to this stream
+                    let par = unsafe { &(*DMA::ptr()).st[$x].par };
+                    par.write(|w| w.pa().bits(value as u32));
+                }
+
+                #[inline(always)]
+                fn set_memory_address(&mut self, value: usize) {
+                    // unsafe: Owned exclusive access to this stream
+                    let m0ar = unsafe { &(*DMA::ptr()).st[$x].m0ar };
+                    m0ar.write(|w| w.m0a().bits(value as u32));
+                }
+
+                #[inline(always)]
+                fn set_number_of_transfers(&mut self, value: u16) {
+                    // unsafe: Owned exclusive access to this stream
+                    let ndtr = unsafe { &(*DMA::ptr()).st[$x].ndtr };
+                    ndtr.write(|w| w.ndt().bits(value));
+                }
+
+                #[inline(always)]
+                fn get_number_of_transfers() -> u16 {
+                    // unsafe: Atomic read with no side effects
+                    let ndtr = unsafe { &(*DMA::ptr()).st[$x].ndtr };
+                    ndtr.read().ndt().bits()
+                }
+
+                #[inline(always)]
+                unsafe fn set_memory_size(&mut self, size: u8) {
+                    Self::cr().modify(|_, w| w.msize().bits(size));
+                }
+
+                #[inline(always)]
+                unsafe fn set_peripheral_size(&mut self, size: u8) {
+                    Self::cr().modify(|_, w| w.psize().bits(size));
+                }
+
+                #[inline(always)]
+                fn set_direction(&mut self, direction: DmaDirection) {
+                    Self::cr().modify(|_, w| match direction {
+                        DmaDirection::PeripheralToMemory => {
+                            w.dir().peripheral_to_memory()
+                        }
+                        DmaDirection::MemoryToPeripheral => {
+                            w.dir().memory_to_peripheral()
+                        }
+                        DmaDirection::MemoryToMemory => {
+                            w.dir().memory_to_memory()
+                        }
+                    });
+                }
+
+                #[inline(always)]
+                fn set_request_line(&mut self, request_line: u8) {
+                    // unsafe: Owned exclusive access to this DMAMUX
+                    // channel
+                    let dmamux = unsafe { &*DMAMUX1::ptr() };
+                    dmamux.ccr[$x + DMA::DMAMUX_STREAM_OFFSET]
+                        .modify(|_, w| unsafe { w.dmareq_id().bits(request_line) });
+                }
+
+                #[inline(always)]
+                unsafe fn enable(&mut self) {
+                    Self::cr().modify(|_, w| w.en().enabled());
+                }
+
+                #[inline(always)]
+                fn is_enabled() -> bool {
+                    Self::cr().read().en().is_enabled()
+                }
+
+                fn disable(&mut self) {
+                    if Self::is_enabled() {
+                        // Aborting an ongoing transfer sets the transfer
+                        // complete flag
+                        let tcie = Self::cr().read().tcie().bit_is_set();
+                        self.set_transfer_complete_interrupt_enable(false);
+
+                        Self::cr().modify(|_, w| w.en().disabled());
+                        while Self::is_enabled() {}
+
+                        self.clear_transfer_complete_interrupt();
+                        self.set_transfer_complete_interrupt_enable(tcie);
+                    }
+                }
+
+                #[inline(always)]
+                fn set_transfer_complete_interrupt_enable(&mut self, enable: bool) {
+                    Self::cr().modify(|_, w| w.tcie().bit(enable));
+                }
+
+                #[inline(always)]
+                fn set_half_transfer_interrupt_enable(&mut self, enable: bool) {
+                    Self::cr().modify(|_, w| w.htie().bit(enable));
+                }
+
+                #[inline(always)]
+                fn set_transfer_error_interrupt_enable(&mut self, enable: bool) {
+                    Self::cr().modify(|_, w| w.teie().bit(enable));
+                }
+            }
+
+            // DMA1 and DMA2 can access all memories except the DTCM and
+            // ITCM. This is not checked
+            unsafe impl<DMA: Instance, BUF> MemoryAccess<BUF>
+                for $StreamX<DMA>
+            {
+            }
+
+            impl<DMA: Instance> DoubleBufferedStream for $StreamX<DMA> {
+                #[inline(always)]
+                fn set_memory_double_buffer_address(&mut self, value: usize) {
+                    // unsafe: Owned exclusive access to this stream
+                    let m1ar = unsafe { &(*DMA::ptr()).st[$x].m1ar };
+                    m1ar.write(|w| w.m1a().bits(value as u32));
+                }
+
+                #[inline(always)]
+                fn set_double_buffer(&mut self, double_buffer: bool) {
+                    Self::cr().modify(|_, w| w.dbm().bit(double_buffer));
+                }
+
+                #[inline(always)]
+                fn get_current_buffer() -> CurrentBuffer {
+                    if Self::cr().read().ct().is_memory0() {
+                        CurrentBuffer::FirstBuffer
+                    } else {
+                        CurrentBuffer::SecondBuffer
+                    }
+                }
+            }
+        )+
+    };
+}
+
+dma_stream! {
+    Stream0: 0, lisr, lifcr, 0;
+    Stream1: 1, lisr, lifcr, 6;
+    Stream2: 2, lisr, lifcr, 16;
+    Stream3: 3, lisr, lifcr, 22;
+    Stream4: 4, hisr, hifcr, 0;
+    Stream5: 5, hisr, hifcr, 6;
+    Stream6: 6, hisr, hifcr, 16;
+    Stream7: 7, hisr, hifcr, 22;
+}
+
+/// All the streams of a DMA controller
+pub struct StreamsTuple<DMA>(
+    pub Stream0<DMA>,
+    pub Stream1<DMA>,
+    pub Stream2<DMA>,
+    pub Stream3<DMA>,
+    pub Stream4<DMA>,
+    pub Stream5<DMA>,
+    pub Stream6<DMA>,
+    pub Stream7<DMA>,
+);
+
+impl<DMA: Instance> StreamsTuple<DMA> {
+    /// Splits the DMA peripheral into streams. The DMA controller is
+    /// enabled and reset.
+    pub fn new(_regs: DMA, prec: DMA::Rec) -> Self {
+        prec.enable().reset();
+
+        Self(
+            Stream0 { _dma: PhantomData },
+            Stream1 { _dma: PhantomData },
+            Stream2 { _dma: PhantomData },
+            Stream3 { _dma: PhantomData },
+            Stream4 { _dma: PhantomData },
+            Stream5 { _dma: PhantomData },
+            Stream6 { _dma: PhantomData },
+            Stream7 { _dma: PhantomData },
+        )
+    }
+}
@@ -0,0 +1,782 @@
+//! Master DMA (MDMA)
+//!
+//! The MDMA controller sits in the D1 domain and can access all memories,
+//! including the ITCM and DTCM through the AHBS bus. It is therefore the
+//! only DMA controller that can move data into or out of the tightly
+//! coupled memories.
+//!
+//! Each of the 16 channels performs memory-to-memory copies that are
+//! triggered by software. A transfer is made up of one or more blocks,
+//! optionally repeated, and further transfers can be chained using
+//! [`LinkedListNode`](struct.LinkedListNode.html)s.
+//!
+//! # Usage
+//!
+//! ```
+//! use stm32h7xx_hal::dma::mdma::{ChannelsTuple, MdmaConfig, MdmaTransfer};
+//!
+//! let channels = ChannelsTuple::new(dp.MDMA, ccdr.peripheral.MDMA);
+//!
+//! // SOURCE is in AXI SRAM, DESTINATION is in DTCM
+//! let mut transfer = MdmaTransfer::init(
+//!     channels.0,
+//!     SOURCE,
+//!     DESTINATION,
+//!     MdmaConfig::default(),
+//! );
+//! transfer.start();
+//!
+//! while !transfer.get_transfer_complete_flag() {}
+//! let (channel, source, destination) = transfer.free();
+//! ```
+//!
+//! The bus used to access each buffer is selected automatically: the
+//! AHBS bus for ITCM and DTCM addresses, and the AXI bus otherwise.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use super::dma::Priority;
+use super::traits::sealed::Sealed;
+use super::{word_size, StaticReadBuffer, StaticWriteBuffer, Word};
+use crate::rcc::{rec, ResetEnable};
+use crate::stm32::{mdma, MDMA};
+
+// Channel interrupt/status register (CxISR) and flag clear register
+// (CxIFCR)
+const TEIF: u32 = 1 << 0;
+const CTCIF: u32 = 1 << 1;
+const BRTIF: u32 = 1 << 2;
+const BTIF: u32 = 1 << 3;
+const TCIF: u32 = 1 << 4;
+const ALL_FLAGS: u32 = TEIF | CTCIF | BRTIF | BTIF | TCIF;
+
+// Channel control register (CxCR)
+const CR_EN: u32 = 1 << 0;
+const CR_TEIE: u32 = 1 << 1;
+const CR_CTCIE: u32 = 1 << 2;
+const CR_BRTIE: u32 = 1 << 3;
+const CR_BTIE: u32 = 1 << 4;
+const CR_TCIE: u32 = 1 << 5;
+const CR_PL_SHIFT: u32 = 6;
+const CR_SWRQ: u32 = 1 << 16;
+const CR_INTERRUPTS: u32 = CR_TEIE | CR_CTCIE | CR_BRTIE | CR_BTIE | CR_TCIE;
+
+// Channel transfer configuration register (CxTCR)
+const TCR_SINC_SHIFT: u32 = 0;
+const TCR_DINC_SHIFT: u32 = 2;
+const TCR_SSIZE_SHIFT: u32 = 4;
+const TCR_DSIZE_SHIFT: u32 = 6;
+const TCR_SINCOS_SHIFT: u32 = 8;
+const TCR_DINCOS_SHIFT: u32 = 10;
+const TCR_SBURST_SHIFT: u32 = 12;
+const TCR_DBURST_SHIFT: u32 = 15;
+const TCR_TLEN_SHIFT: u32 = 18;
+const TCR_TRGM_SHIFT: u32 = 28;
+const TCR_SWRM: u32 = 1 << 30;
+
+// Channel block number of data register (CxBNDTR)
+const BNDTR_BRSUM: u32 = 1 << 18;
+const BNDTR_BRDUM: u32 = 1 << 19;
+const BNDTR_BRC_SHIFT: u32 = 20;
+
+// Channel trigger and bus selection register (CxTBR)
+const TBR_SBUS: u32 = 1 << 16;
+const TBR_DBUS: u32 = 1 << 17;
+
+/// Maximum number of bytes in a block
+pub const MAX_BLOCK_BYTES: usize = 65536;
+
+/// Registers of a single MDMA channel. All channels have the same layout
+/// as channel 0
+#[repr(C)]
+struct ChannelRegisterBlock {
+    isr: mdma::C0ISR,
+    ifcr: mdma::C0IFCR,
+    esr: mdma::C0ESR,
+    cr: mdma::C0CR,
+    tcr: mdma::C0TCR,
+    bndtr: mdma::C0BNDTR,
+    sar: mdma::C0SAR,
+    dar: mdma::C0DAR,
+    brur: mdma::C0BRUR,
+    lar: mdma::C0LAR,
+    tbr: mdma::C0TBR,
+    _reserved: u32,
+    mar: mdma::C0MAR,
+    mdr: mdma::C0MDR,
+}
+
+/// Returns true if `address` is in the ITCM or DTCM, and so must be
+/// accessed through the AHBS bus
+fn is_tcm(address: usize) -> bool {
+    const ITCM: (usize, usize) = (0x0000_0000, 0x0001_0000);
+    const DTCM: (usize, usize) = (0x2000_0000, 0x2002_0000);
+
+    (address >= ITCM.0 && address < ITCM.1)
+        || (address >= DTCM.0 && address < DTCM.1)
+}
+
+/// Address increment mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MdmaIncrement {
+    /// The address is not changed
+    Fixed,
+    /// The address is incremented by the word size after each word
+    Increment,
+    /// The address is decremented by the word size after each word
+    Decrement,
+}
+
+impl MdmaIncrement {
+    fn bits(self) -> u32 {
+        match self {
+            MdmaIncrement::Fixed => 0b00,
+            MdmaIncrement::Increment => 0b10,
+            MdmaIncrement::Decrement => 0b11,
+        }
+    }
+}
+
+/// Number of beats in a burst
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MdmaBurstSize {
+    /// Single transfer
+    Single,
+    /// Burst of 2 beats
+    Beats2,
+    /// Burst of 4 beats
+    Beats4,
+    /// Burst of 8 beats
+    Beats8,
+    /// Burst of 16 beats
+    Beats16,
+    /// Burst of 32 beats
+    Beats32,
+    /// Burst of 64 beats
+    Beats64,
+    /// Burst of 128 beats
+    Beats128,
+}
+
+impl MdmaBurstSize {
+    fn bits(self) -> u32 {
+        match self {
+            MdmaBurstSize::Single => 0,
+            MdmaBurstSize::Beats2 => 1,
+            MdmaBurstSize::Beats4 => 2,
+            MdmaBurstSize::Beats8 => 3,
+            MdmaBurstSize::Beats16 => 4,
+            MdmaBurstSize::Beats32 => 5,
+            MdmaBurstSize::Beats64 => 6,
+            MdmaBurstSize::Beats128 => 7,
+        }
+    }
+}
+
+/// The amount of data transferred for each software request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MdmaTrigger {
+    /// Each request transfers one buffer (`buffer_length` bytes)
+    Buffer,
+    /// Each request transfers one block
+    Block,
+    /// Each request transfers all the repetitions of a block
+    RepeatedBlock,
+    /// Each request transfers the whole linked list
+    LinkedList,
+}
+
+impl MdmaTrigger {
+    fn bits(self) -> u32 {
+        match self {
+            MdmaTrigger::Buffer => 0b00,
+            MdmaTrigger::Block => 0b01,
+            MdmaTrigger::RepeatedBlock => 0b10,
+            MdmaTrigger::LinkedList => 0b11,
+        }
+    }
+}
+
+/// Configuration for a MDMA channel.
+///
+/// This structure uses builder semantics to generate the configuration.
+///
+/// `Example`
+/// ```
+/// let config = MdmaConfig::default()
+///     .priority(Priority::High)
+///     .source_burst(MdmaBurstSize::Beats16)
+///     .destination_burst(MdmaBurstSize::Beats16);
+/// ```
+///
+/// By default both addresses are incremented, a single software request
+/// transfers the whole linked list, and the buffer length is 128 bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct MdmaConfig {
+    priority: Priority,
+    source_increment: MdmaIncrement,
+    destination_increment: MdmaIncrement,
+    source_burst: MdmaBurstSize,
+    destination_burst: MdmaBurstSize,
+    buffer_length: u8,
+    trigger: MdmaTrigger,
+    block_repeat: u16,
+    source_block_offset: i32,
+    destination_block_offset: i32,
+    transfer_complete_interrupt: bool,
+    block_complete_interrupt: bool,
+    block_repeat_complete_interrupt: bool,
+    buffer_complete_interrupt: bool,
+    transfer_error_interrupt: bool,
+}
+
+impl Default for MdmaConfig {
+    fn default() -> Self {
+        Self {
+            priority: Priority::Medium,
+            source_increment: MdmaIncrement::Increment,
+            destination_increment: MdmaIncrement::Increment,
+            source_burst: MdmaBurstSize::Single,
+            destination_burst: MdmaBurstSize::Single,
+            buffer_length: 128,
+            trigger: MdmaTrigger::LinkedList,
+            block_repeat: 1,
+            source_block_offset: 0,
+            destination_block_offset: 0,
+            transfer_complete_interrupt: false,
+            block_complete_interrupt: false,
+            block_repeat_complete_interrupt: false,
+            buffer_complete_interrupt: false,
+            transfer_error_interrupt: false,
+        }
+    }
+}
+
+impl MdmaConfig {
+    /// Set the priority of the channel
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Set the increment mode of the source address
+    pub fn source_increment(mut self, increment: MdmaIncrement) -> Self {
+        self.source_increment = increment;
+        self
+    }
+    /// Set the increment mode of the destination address
+    pub fn destination_increment(mut self, increment: MdmaIncrement) -> Self {
+        self.destination_increment = increment;
+        self
+    }
+    /// Set the burst size used to read the source
+    pub fn source_burst(mut self, burst: MdmaBurstSize) -> Self {
+        self.source_burst = burst;
+        self
+    }
+    /// Set the burst size used to write the destination
+    pub fn destination_burst(mut self, burst: MdmaBurstSize) -> Self {
+        self.destination_burst = burst;
+        self
+    }
+    /// Set the number of bytes in a buffer, 1 - 128. The buffer length
+    /// must be a multiple of the word size, and no smaller than a burst.
+    pub fn buffer_length(mut self, bytes: u8) -> Self {
+        assert!((1..=128).contains(&bytes));
+        self.buffer_length = bytes;
+        self
+    }
+    /// Set the amount of data that is transferred for each software
+    /// request
+    pub fn trigger(mut self, trigger: MdmaTrigger) -> Self {
+        self.trigger = trigger;
+        self
+    }
+    /// Repeat the block `count` times, 1 - 4096. After each repetition
+    /// the source and destination addresses are moved by
+    /// `source_offset` and `destination_offset` bytes respectively.
+    ///
+    /// The source and destination buffers are divided into `count`
+    /// blocks of equal length.
+    pub fn block_repeat(
+        mut self,
+        count: u16,
+        source_offset: i32,
+        destination_offset: i32,
+    ) -> Self {
+        assert!((1..=4096).contains(&count));
+        assert!(source_offset.abs() <= 0xFFFF);
+        assert!(destination_offset.abs() <= 0xFFFF);
+        self.block_repeat = count;
+        self.source_block_offset = source_offset;
+        self.destination_block_offset = destination_offset;
+        self
+    }
+    /// Enable the channel transfer complete interrupt. This occurs when
+    /// the last block of the last node of the linked list is complete
+    pub fn transfer_complete_interrupt(mut self, enable: bool) -> Self {
+        self.transfer_complete_interrupt = enable;
+        self
+    }
+    /// Enable the block transfer complete interrupt
+    pub fn block_complete_interrupt(mut self, enable: bool) -> Self {
+        self.block_complete_interrupt = enable;
+        self
+    }
+    /// Enable the block repeat transfer complete interrupt
+    pub fn block_repeat_complete_interrupt(mut self, enable: bool) -> Self {
+        self.block_repeat_complete_interrupt = enable;
+        self
+    }
+    /// Enable the buffer transfer complete interrupt
+    pub fn buffer_complete_interrupt(mut self, enable: bool) -> Self {
+        self.buffer_complete_interrupt = enable;
+        self
+    }
+    /// Enable the transfer error interrupt
+    pub fn transfer_error_interrupt(mut self, enable: bool) -> Self {
+        self.transfer_error_interrupt = enable;
+        self
+    }
+
+    /// Value of the CxCR register, without EN
+    fn cr(&self) -> u32 {
+        let mut cr = (self.priority.bits() as u32) << CR_PL_SHIFT;
+        if self.transfer_error_interrupt {
+            cr |= CR_TEIE;
+        }
+        if self.transfer_complete_interrupt {
+            cr |= CR_CTCIE;
+        }
+        if self.block_repeat_complete_interrupt {
+            cr |= CR_BRTIE;
+        }
+        if self.block_complete_interrupt {
+            cr |= CR_BTIE;
+        }
+        if self.buffer_complete_interrupt {
+            cr |= CR_TCIE;
+        }
+        cr
+    }
+}
+
+/// A node in a linked list of MDMA transfers.
+///
+/// The layout of a node matches the CxTCR - CxMDR registers of a channel,
+/// which the MDMA controller loads from the node when the previous
+/// transfer completes.
+#[repr(C, align(8))]
+#[derive(Debug, Clone, Copy)]
+pub struct LinkedListNode {
+    tcr: u32,
+    bndtr: u32,
+    sar: u32,
+    dar: u32,
+    brur: u32,
+    lar: u32,
+    tbr: u32,
+    _reserved: u32,
+    mar: u32,
+    mdr: u32,
+}
+
+impl LinkedListNode {
+    /// Creates a node that copies `source` to `destination`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffers have different lengths, if they cannot be
+    /// divided into the number of blocks given in `config`, or if the
+    /// blocks are longer than 65536 bytes.
+    ///
+    /// # Safety
+    ///
+    /// Both buffers must remain valid, and `destination` must not be
+    /// accessed by software, for as long as the node is part of a
+    /// transfer.
+    pub unsafe fn memory_to_memory<W: Word>(
+        source: &[W],
+        destination: &mut [W],
+        config: &MdmaConfig,
+    ) -> Self {
+        Self::new(
+            source.as_ptr() as usize,
+            destination.as_mut_ptr() as usize,
+            source.len(),
+            destination.len(),
+            word_size::<W>() as u32,
+            config,
+        )
+    }
+
+    fn new(
+        source: usize,
+        destination: usize,
+        source_len: usize,
+        destination_len: usize,
+        size: u32,
+        config: &MdmaConfig,
+    ) -> Self {
+        assert_eq!(source_len, destination_len, "Buffer lengths must be equal");
+
+        let bytes = source_len << size;
+        let count = config.block_repeat as usize;
+        let block_bytes = bytes / count;
+        assert!(
+            block_bytes * count == bytes,
+            "Buffers must divide into blocks"
+        );
+        assert!(block_bytes <= MAX_BLOCK_BYTES, "Block too long");
+        assert!(config.buffer_length as u32 & ((1 << size) - 1) == 0);
+
+        // Word size is the same for source and destination. The address
+        // offset is equal to the word size
+        let tcr = (config.source_increment.bits() << TCR_SINC_SHIFT)
+            | (config.destination_increment.bits() << TCR_DINC_SHIFT)
+            | (size << TCR_SSIZE_SHIFT)
+            | (size << TCR_DSIZE_SHIFT)
+            | (size << TCR_SINCOS_SHIFT)
+            | (size << TCR_DINCOS_SHIFT)
+            | (config.source_burst.bits() << TCR_SBURST_SHIFT)
+            | (config.destination_burst.bits() << TCR_DBURST_SHIFT)
+            | ((config.buffer_length as u32 - 1) << TCR_TLEN_SHIFT)
+            | (config.trigger.bits() << TCR_TRGM_SHIFT)
+            | TCR_SWRM;
+
+        let mut bndtr = (block_bytes as u32 & 0x1_FFFF)
+            | ((count as u32 - 1) << BNDTR_BRC_SHIFT);
+        if config.source_block_offset < 0 {
+            bndtr |= BNDTR_BRSUM;
+        }
+        if config.destination_block_offset < 0 {
+            bndtr |= BNDTR_BRDUM;
+        }
+        let magnitude = |offset: i32| {
+            if offset < 0 {
+                -offset as u32
+            } else {
+                offset as u32
+            }
+        };
+        let brur = magnitude(config.source_block_offset)
+            | (magnitude(config.destination_block_offset) << 16);
+
+        let mut tbr = 0;
+        if is_tcm(source) {
+            tbr |= TBR_SBUS;
+        }
+        if is_tcm(destination) {
+            tbr |= TBR_DBUS;
+        }
+
+        LinkedListNode {
+            tcr,
+            bndtr,
+            sar: source as u32,
+            dar: destination as u32,
+            brur,
+            lar: 0,
+            tbr,
+            _reserved: 0,
+            mar: 0,
+            mdr: 0,
+        }
+    }
+
+    /// Sets the node that is loaded after this node completes.
+    ///
+    /// The MDMA controller reads the next node over the AXI bus, so it
+    /// must not be located in the DTCM or ITCM.
+    pub fn link(&mut self, next: &'static LinkedListNode) {
+        let address = next as *const _ as usize;
+        debug_assert!(!is_tcm(address));
+        self.lar = address as u32;
+    }
+
+    /// Makes this node the last node of the linked list
+    pub fn unlink(&mut self) {
+        self.lar = 0;
+    }
+}
+
+/// Trait for MDMA channels
+pub trait MdmaChannel: Sealed {
+    /// Channel number
+    const NUMBER: usize;
+}
+
+/// MDMA Channel
+pub struct Channel<N> {
+    _channel: PhantomData<N>,
+}
+
+impl<N> Sealed for Channel<N> {}
+
+impl<N> Channel<N>
+where
+    Channel<N>: MdmaChannel,
+{
+    #[inline(always)]
+    fn regs(&self) -> &ChannelRegisterBlock {
+        let address = MDMA::ptr() as usize + 0x40 + 0x40 * Self::NUMBER;
+
+        // unsafe: Owned exclusive access to the registers of this channel
+        unsafe { &*(address as *const ChannelRegisterBlock) }
+    }
+
+    /// Clears all interrupt flags of the channel
+    pub fn clear_interrupts(&mut self) {
+        self.regs().ifcr.write(|w| unsafe { w.bits(ALL_FLAGS) });
+    }
+
+    /// Returns true if the channel transfer complete flag is set. This is
+    /// set when the whole linked list is complete
+    pub fn get_transfer_complete_flag(&self) -> bool {
+        self.regs().isr.read().bits() & CTCIF != 0
+    }
+
+    /// Returns true if the block transfer complete flag is set
+    pub fn get_block_complete_flag(&self) -> bool {
+        self.regs().isr.read().bits() & BTIF != 0
+    }
+
+    /// Returns true if the block repeat transfer complete flag is set
+    pub fn get_block_repeat_complete_flag(&self) -> bool {
+        self.regs().isr.read().bits() & BRTIF != 0
+    }
+
+    /// Returns true if the buffer transfer complete flag is set
+    pub fn get_buffer_complete_flag(&self) -> bool {
+        self.regs().isr.read().bits() & TCIF != 0
+    }
+
+    /// Returns true if the transfer error flag is set
+    pub fn get_transfer_error_flag(&self) -> bool {
+        self.regs().isr.read().bits() & TEIF != 0
+    }
+
+    /// Returns the contents of the error status register (CxESR)
+    pub fn get_error_status(&self) -> u32 {
+        self.regs().esr.read().bits()
+    }
+
+    /// Returns true if the channel is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.regs().cr.read().bits() & CR_EN != 0
+    }
+
+    /// Disables the channel, and waits for the current transfer to stop
+    pub fn disable(&mut self) {
+        let regs = self.regs();
+        regs.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_EN) });
+        while regs.cr.read().bits() & CR_EN != 0 {}
+    }
+
+    /// Loads the registers of the channel from `node`, and applies the
+    /// interrupt and priority settings of `config`
+    fn load(&mut self, node: &LinkedListNode, config: &MdmaConfig) {
+        self.disable();
+        self.clear_interrupts();
+
+        let regs = self.regs();
+        unsafe {
+            regs.tcr.write(|w| w.bits(node.tcr));
+            regs.bndtr.write(|w| w.bits(node.bndtr));
+            regs.sar.write(|w| w.bits(node.sar));
+            regs.dar.write(|w| w.bits(node.dar));
+            regs.brur.write(|w| w.bits(node.brur));
+            regs.lar.write(|w| w.bits(node.lar));
+            regs.tbr.write(|w| w.bits(node.tbr));
+            regs.mar.write(|w| w.bits(node.mar));
+            regs.mdr.write(|w| w.bits(node.mdr));
+            regs.cr.write(|w| w.bits(config.cr()));
+        }
+    }
+
+    /// Enables the channel and issues a software request
+    fn enable_and_request(&mut self) {
+        let regs = self.regs();
+        regs.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_EN) });
+        regs.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_SWRQ) });
+    }
+
+    /// Issues a software request. When the trigger mode is not
+    /// `MdmaTrigger::LinkedList`, a request is needed for each buffer,
+    /// block or repeated block.
+    pub fn request(&mut self) {
+        let regs = self.regs();
+        regs.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_SWRQ) });
+    }
+
+    /// Enables or disables all the interrupts of this channel that are
+    /// set in `config`
+    pub fn listen(&mut self, config: &MdmaConfig, enable: bool) {
+        let interrupts = config.cr() & CR_INTERRUPTS;
+        self.regs().cr.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() | interrupts)
+            } else {
+                w.bits(r.bits() & !interrupts)
+            }
+        });
+    }
+}
+
+macro_rules! mdma_channels {
+    ($($N:ident: $x:expr,)+) => {
+        $(
+            /// Marker type for an MDMA channel number
+            pub struct $N;
+
+            impl MdmaChannel for Channel<$N> {
+                const NUMBER: usize = $x;
+            }
+        )+
+
+        /// All the channels of the MDMA controller
+        pub struct ChannelsTuple(
+            $(
+                pub Channel<$N>,
+            )+
+        );
+
+        impl ChannelsTuple {
+            /// Splits the MDMA peripheral into channels. The MDMA
+            /// controller is enabled and reset.
+            pub fn new(_regs: MDMA, prec: rec::Mdma) -> Self {
+                prec.enable().reset();
+
+                ChannelsTuple(
+                    $(
+                        Channel::<$N> { _channel: PhantomData },
+                    )+
+                )
+            }
+        }
+    };
+}
+
+mdma_channels! {
+    C0: 0, C1: 1, C2: 2, C3: 3, C4: 4, C5: 5, C6: 6, C7: 7,
+    C8: 8, C9: 9, C10: 10, C11: 11, C12: 12, C13: 13, C14: 14, C15: 15,
+}
+
+/// MDMA memory-to-memory transfer.
+///
+/// The transfer owns the channel and both buffers until it is freed.
+pub struct MdmaTransfer<N, SRC, DST>
+where
+    Channel<N>: MdmaChannel,
+{
+    channel: Channel<N>,
+    source: SRC,
+    destination: DST,
+}
+
+impl<N, SRC, DST> MdmaTransfer<N, SRC, DST>
+where
+    Channel<N>: MdmaChannel,
+    SRC: StaticReadBuffer,
+    DST: StaticWriteBuffer<Word = SRC::Word>,
+{
+    /// Configures the channel to copy `source` to `destination`. The
+    /// channel is not enabled until `start` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffers have different lengths, if they cannot be
+    /// divided into the number of blocks given in `config`, or if the
+    /// blocks are longer than 65536 bytes.
+    pub fn init(
+        mut channel: Channel<N>,
+        source: SRC,
+        mut destination: DST,
+        config: MdmaConfig,
+    ) -> Self {
+        let (src_ptr, src_len) = source.static_read_buffer();
+        let (dst_ptr, dst_len) = destination.static_write_buffer();
+
+        let node = LinkedListNode::new(
+            src_ptr as usize,
+            dst_ptr as usize,
+            src_len,
+            dst_len,
+            word_size::<SRC::Word>() as u32,
+            &config,
+        );
+        channel.load(&node, &config);
+
+        MdmaTransfer {
+            channel,
+            source,
+            destination,
+        }
+    }
+
+    /// Continues the transfer with `next` once the buffers owned by this
+    /// transfer are complete. The channel transfer complete flag is only
+    /// set after the last node of the linked list.
+    ///
+    /// # Safety
+    ///
+    /// The buffers referenced by `next`, and by all nodes linked from
+    /// it, must remain valid until the transfer is complete.
+    pub unsafe fn link(&mut self, next: &'static LinkedListNode) {
+        let address = next as *const _ as usize;
+        debug_assert!(!is_tcm(address));
+        self.channel.regs().lar.write(|w| w.bits(address as u32));
+    }
+
+    /// Starts the transfer
+    pub fn start(&mut self) {
+        // "Preceding reads and writes cannot be moved past subsequent
+        // writes"
+        compiler_fence(Ordering::Release);
+
+        self.channel.enable_and_request();
+    }
+
+    /// Returns true if the whole transfer, including all linked nodes,
+    /// is complete
+    pub fn get_transfer_complete_flag(&self) -> bool {
+        self.channel.get_transfer_complete_flag()
+    }
+
+    /// Returns true if the transfer error flag is set
+    pub fn get_transfer_error_flag(&self) -> bool {
+        self.channel.get_transfer_error_flag()
+    }
+
+    /// Access the channel, for example to issue further software
+    /// requests or to read other flags
+    pub fn channel(&mut self) -> &mut Channel<N> {
+        &mut self.channel
+    }
+
+    /// Stops the transfer, and returns the channel and both buffers
+    pub fn free(mut self) -> (Channel<N>, SRC, DST) {
+        self.channel.disable();
+        compiler_fence(Ordering::SeqCst);
+        self.channel.clear_interrupts();
+
+        unsafe {
+            let channel = ptr::read(&self.channel);
+            let source = ptr::read(&self.source);
+            let destination = ptr::read(&self.destination);
+            mem::forget(self);
+            (channel, source, destination)
+        }
+    }
+}
+
+impl<N, SRC, DST> Drop for MdmaTransfer<N, SRC, DST>
+where
+    Channel<N>: MdmaChannel,
+{
+    fn drop(&mut self) {
+        self.channel.disable();
+    }
+}
@@ -0,0 +1,865 @@
+//! Direct Memory Access (DMA)
+//!
+//! This module implements one-shot and circular transfers between memory
+//! and peripherals using the DMA1 and DMA2 controllers. Each controller has
+//! eight streams, and each stream is routed to a peripheral request line
+//! by the DMA request multiplexer (DMAMUX1).
+//!
+//! Peripherals in the D3 domain (LPUART1, SPI6, I2C4, SAI4 and ADC3) are
+//! served by the [BDMA](bdma/index.html) controller, whose channels are
+//! routed by DMAMUX2. BDMA channels are used with the same `Transfer` type.
+//! Each target names the multiplexer that routes its request line in
+//! `TargetAddress::Mux`, so that a target can only be used with a stream
+//! served by the same multiplexer.
+//!
+//! Memory-to-memory transfers that involve the DTCM or ITCM can use the
+//! [MDMA](mdma/index.html) controller instead.
+//!
+//! # Usage
+//!
+//! ```
+//! use stm32h7xx_hal::dma::{self, dma::{DmaConfig, StreamsTuple}, Transfer};
+//!
+//! // Take the streams of DMA1. This enables and resets DMA1
+//! let streams = StreamsTuple::new(dp.DMA1, ccdr.peripheral.DMA1);
+//!
+//! let config = DmaConfig::default()
+//!     .memory_increment(true)
+//!     .priority(dma::dma::Priority::High);
+//!
+//! // `BUFFER` is a `&'static mut [u8]`, and `target` is any type that
+//! // implements `TargetAddress<MemoryToPeripheral>`
+//! let mut transfer: Transfer<_, _, dma::MemoryToPeripheral, _> =
+//!     Transfer::init(streams.0, target, BUFFER, config);
+//!
+//! // Enable the stream, then enable DMA requests in the peripheral
+//! transfer.start(|_target| {});
+//!
+//! while !transfer.get_transfer_complete_flag() {}
+//!
+//! let (stream, target, buffer) = transfer.free();
+//! ```
+//!
+//! # Double Buffering
+//!
+//! A [`DoubleBufferedTransfer`](struct.DoubleBufferedTransfer.html)
+//! alternates between two buffers, so that software can process one
+//! buffer while the DMA controller fills or empties the other.
+//!
+//! ```
+//! let mut transfer: DoubleBufferedTransfer<_, _, PeripheralToMemory, _> =
+//!     DoubleBufferedTransfer::init(streams.1, target, BUF0, BUF1, config);
+//!
+//! transfer.listen(dma::Event::TransferComplete);
+//! transfer.start(|_target| {});
+//!
+//! // In the DMA interrupt handler
+//! match transfer.next_transfer_with(|buf, _current| process(buf)) {
+//!     Ok(_) => {}
+//!     Err(dma::DmaError::Overrun) => { /* software was too slow */ }
+//!     Err(_) => {}
+//! }
+//! ```
+//!
+//! # Buffers
+//!
+//! The `Transfer` takes ownership of the memory buffer until it is freed,
+//! so that the buffer cannot be accessed by software while the DMA
+//! controller is using it. Buffers must have a `'static` lifetime: since
+//! `mem::forget` is safe, a shorter borrow could end while a transfer is
+//! still running.
+//!
+//! Note that DMA1 and DMA2 cannot access the DTCM or ITCM memories. By
+//! default the linker places statics in DTCM, so DMA buffers should be
+//! placed in AXI SRAM or SRAM1/2/3 using a `#[link_section]` attribute.
+//!
+//! BDMA can only access SRAM4. BDMA transfers only accept buffers wrapped
+//! in [`Sram4`](bdma/struct.Sram4.html), which are created by the
+//! [`sram4_buffer!`](../macro.sram4_buffer.html) macro.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+pub mod bdma; // BDMA
+#[allow(clippy::module_inception)]
+pub mod dma; // DMA1 and DMA2
+pub mod mdma; // MDMA
+pub mod traits;
+
+use traits::{
+    Direction, DoubleBufferedStream, MemoryAccess, Stream, TargetAddress,
+};
+
+/// Possible directions of a DMA transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DmaDirection {
+    /// Memory to Memory transfer
+    MemoryToMemory,
+    /// Peripheral to Memory transfer
+    PeripheralToMemory,
+    /// Memory to Peripheral transfer
+    MemoryToPeripheral,
+}
+
+/// DMA from a peripheral to a memory location (type state)
+#[derive(Debug, Clone, Copy)]
+pub struct PeripheralToMemory;
+
+impl Direction for PeripheralToMemory {
+    #[inline(always)]
+    fn direction() -> DmaDirection {
+        DmaDirection::PeripheralToMemory
+    }
+}
+
+/// DMA from a memory location to a peripheral (type state)
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryToPeripheral;
+
+impl Direction for MemoryToPeripheral {
+    #[inline(always)]
+    fn direction() -> DmaDirection {
+        DmaDirection::MemoryToPeripheral
+    }
+}
+
+/// Errors
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DmaError {
+    /// The DMA controller has not yet completed a buffer
+    NotReady,
+    /// The new buffer is shorter than the buffer it replaces
+    SmallBuffer,
+    /// The DMA controller started to use a buffer before software
+    /// finished with it
+    Overrun,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// DMA interrupt events
+///
+/// Each event is a possible interrupt source, if enabled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Half of the buffer has been transferred (HTIE)
+    HalfTransfer,
+    /// The whole buffer has been transferred (TCIE)
+    TransferComplete,
+    /// A bus error occurred during the transfer (TEIE)
+    TransferError,
+}
+
+fn listen<STREAM: Stream>(stream: &mut STREAM, event: Event, enable: bool) {
+    match event {
+        Event::HalfTransfer => {
+            stream.set_half_transfer_interrupt_enable(enable)
+        }
+        Event::TransferComplete => {
+            stream.set_transfer_complete_interrupt_enable(enable)
+        }
+        Event::TransferError => {
+            stream.set_transfer_error_interrupt_enable(enable)
+        }
+    }
+}
+
+/// The memory buffer that the DMA controller is currently using in double
+/// buffer mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurrentBuffer {
+    /// The buffer at the memory 0 address (M0AR)
+    FirstBuffer,
+    /// The buffer at the memory 1 address (M1AR)
+    SecondBuffer,
+}
+
+impl CurrentBuffer {
+    /// Returns the other buffer
+    pub fn other(self) -> Self {
+        match self {
+            CurrentBuffer::FirstBuffer => CurrentBuffer::SecondBuffer,
+            CurrentBuffer::SecondBuffer => CurrentBuffer::FirstBuffer,
+        }
+    }
+}
+
+/// A word that can be transferred by DMA.
+///
+/// # Safety
+///
+/// Implementing types must be 1, 2 or 4 bytes wide, and must be valid for
+/// every possible bit pattern.
+pub unsafe trait Word: Copy {}
+
+unsafe impl Word for u8 {}
+unsafe impl Word for i8 {}
+unsafe impl Word for u16 {}
+unsafe impl Word for i16 {}
+unsafe impl Word for u32 {}
+unsafe impl Word for i32 {}
+
+/// A buffer that can be read by DMA, and remains valid for as long as
+/// the transfer exists
+///
+/// # Safety
+///
+/// `static_read_buffer` must always return the same pointer and length,
+/// and that memory must remain valid for the `'static` lifetime.
+pub unsafe trait StaticReadBuffer {
+    /// The type of each element in the buffer
+    type Word: Word;
+
+    /// Returns a pointer to the start of the buffer and its length in
+    /// words
+    fn static_read_buffer(&self) -> (*const Self::Word, usize);
+}
+
+/// A buffer that can be written by DMA, and remains valid for as long as
+/// the transfer exists
+///
+/// # Safety
+///
+/// `static_write_buffer` must always return the same pointer and length,
+/// and that memory must remain valid for the `'static` lifetime.
+pub unsafe trait StaticWriteBuffer {
+    /// The type of each element in the buffer
+    type Word: Word;
+
+    /// Returns a pointer to the start of the buffer and its length in
+    /// words
+    fn static_write_buffer(&mut self) -> (*mut Self::Word, usize);
+}
+
+unsafe impl<W: Word> StaticReadBuffer for &'static [W] {
+    type Word = W;
+
+    fn static_read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: Word> StaticReadBuffer for &'static mut [W] {
+    type Word = W;
+
+    fn static_read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: Word> StaticWriteBuffer for &'static mut [W] {
+    type Word = W;
+
+    fn static_write_buffer(&mut self) -> (*mut W, usize) {
+        (self.as_mut_ptr(), self.len())
+    }
+}
+
+/// Encoding of the size of `W` in the MSIZE / PSIZE fields
+#[inline(always)]
+pub(crate) fn word_size<W>() -> u8 {
+    match mem::size_of::<W>() {
+        1 => 0b00,
+        2 => 0b01,
+        4 => 0b10,
+        _ => unreachable!(),
+    }
+}
+
+/// Configures `stream` for a transfer between `peripheral` and the
+/// buffer at `buf_addr`. The stream is left disabled.
+fn configure_stream<STREAM, PERIPHERAL, DIR>(
+    stream: &mut STREAM,
+    peripheral: &PERIPHERAL,
+    buf_addr: usize,
+    buf_len: usize,
+    config: STREAM::Config,
+) where
+    STREAM: Stream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    assert!(buf_len <= 65535, "Buffer too long for a DMA transfer");
+
+    stream.disable();
+    stream.clear_interrupts();
+
+    stream.set_peripheral_address(peripheral.address());
+    stream.set_memory_address(buf_addr);
+    stream.set_number_of_transfers(buf_len as u16);
+
+    // Memory and peripheral word sizes are the same
+    let size = word_size::<PERIPHERAL::MemSize>();
+    unsafe {
+        stream.set_memory_size(size);
+        stream.set_peripheral_size(size);
+    }
+
+    if let Some(request_line) = PERIPHERAL::REQUEST_LINE {
+        stream.set_request_line(request_line);
+    }
+    stream.set_direction(DIR::direction());
+    stream.apply_config(config);
+}
+
+/// DMA Transfer.
+///
+/// The transfer owns the stream, the peripheral and the memory buffer
+/// until it is freed.
+pub struct Transfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: Stream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    stream: STREAM,
+    peripheral: PERIPHERAL,
+    _direction: PhantomData<DIR>,
+    buf: BUF,
+}
+
+impl<STREAM, PERIPHERAL, BUF>
+    Transfer<STREAM, PERIPHERAL, MemoryToPeripheral, BUF>
+where
+    STREAM: Stream + MemoryAccess<BUF>,
+    PERIPHERAL: TargetAddress<MemoryToPeripheral, Mux = STREAM::Mux>,
+    BUF: StaticReadBuffer<
+        Word = <PERIPHERAL as TargetAddress<MemoryToPeripheral>>::MemSize,
+    >,
+{
+    /// Configures the DMA stream for a transfer from `buf` to the
+    /// peripheral. The stream is not enabled until `start` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is longer than 65535 words
+    pub fn init(
+        stream: STREAM,
+        peripheral: PERIPHERAL,
+        buf: BUF,
+        config: STREAM::Config,
+    ) -> Self {
+        let (buf_ptr, buf_len) = buf.static_read_buffer();
+
+        Self::init_common(
+            stream,
+            peripheral,
+            buf,
+            buf_ptr as usize,
+            buf_len,
+            config,
+        )
+    }
+}
+
+impl<STREAM, PERIPHERAL, BUF>
+    Transfer<STREAM, PERIPHERAL, PeripheralToMemory, BUF>
+where
+    STREAM: Stream + MemoryAccess<BUF>,
+    PERIPHERAL: TargetAddress<PeripheralToMemory, Mux = STREAM::Mux>,
+    BUF: StaticWriteBuffer<
+        Word = <PERIPHERAL as TargetAddress<PeripheralToMemory>>::MemSize,
+    >,
+{
+    /// Configures the DMA stream for a transfer from the peripheral to
+    /// `buf`. The stream is not enabled until `start` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is longer than 65535 words
+    pub fn init(
+        stream: STREAM,
+        peripheral: PERIPHERAL,
+        mut buf: BUF,
+        config: STREAM::Config,
+    ) -> Self {
+        let (buf_ptr, buf_len) = buf.static_write_buffer();
+
+        Self::init_common(
+            stream,
+            peripheral,
+            buf,
+            buf_ptr as usize,
+            buf_len,
+            config,
+        )
+    }
+}
+
+impl<STREAM, PERIPHERAL, DIR, BUF> Transfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: Stream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    fn init_common(
+        mut stream: STREAM,
+        peripheral: PERIPHERAL,
+        buf: BUF,
+        buf_addr: usize,
+        buf_len: usize,
+        config: STREAM::Config,
+    ) -> Self {
+        configure_stream::<_, _, DIR>(
+            &mut stream,
+            &peripheral,
+            buf_addr,
+            buf_len,
+            config,
+        );
+
+        Transfer {
+            stream,
+            peripheral,
+            _direction: PhantomData,
+            buf,
+        }
+    }
+
+    /// Starts the transfer. The closure is called after the stream is
+    /// enabled, and should enable DMA requests in the peripheral.
+    pub fn start<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut PERIPHERAL),
+    {
+        // "Preceding reads and writes cannot be moved past subsequent
+        // writes"
+        compiler_fence(Ordering::Release);
+
+        unsafe {
+            self.stream.enable();
+        }
+        f(&mut self.peripheral);
+    }
+
+    /// Returns true if the transfer complete flag is set
+    pub fn get_transfer_complete_flag(&self) -> bool {
+        STREAM::get_transfer_complete_flag()
+    }
+
+    /// Returns true if the half transfer flag is set
+    pub fn get_half_transfer_flag(&self) -> bool {
+        STREAM::get_half_transfer_flag()
+    }
+
+    /// Returns true if the transfer error flag is set
+    pub fn get_transfer_error_flag(&self) -> bool {
+        STREAM::get_transfer_error_flag()
+    }
+
+    /// Clears all the interrupt flags of the stream
+    pub fn clear_interrupts(&mut self) {
+        self.stream.clear_interrupts();
+    }
+
+    /// Clears the transfer complete flag
+    pub fn clear_transfer_complete_interrupt(&mut self) {
+        self.stream.clear_transfer_complete_interrupt();
+    }
+
+    /// Clears the half transfer flag
+    pub fn clear_half_transfer_interrupt(&mut self) {
+        self.stream.clear_half_transfer_interrupt();
+    }
+
+    /// Returns the number of words that remain to be transferred
+    pub fn get_number_of_transfers(&self) -> u16 {
+        STREAM::get_number_of_transfers()
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        listen(&mut self.stream, event, true);
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        listen(&mut self.stream, event, false);
+    }
+
+    /// Stops the transfer, and returns the stream, the peripheral and
+    /// the buffer
+    pub fn free(mut self) -> (STREAM, PERIPHERAL, BUF) {
+        self.stream.disable();
+        compiler_fence(Ordering::SeqCst);
+        self.stream.clear_interrupts();
+
+        unsafe {
+            let stream = ptr::read(&self.stream);
+            let peripheral = ptr::read(&self.peripheral);
+            let buf = ptr::read(&self.buf);
+            mem::forget(self);
+            (stream, peripheral, buf)
+        }
+    }
+}
+
+impl<STREAM, PERIPHERAL, DIR, BUF> Drop
+    for Transfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: Stream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    fn drop(&mut self) {
+        self.stream.disable();
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Circular DMA transfer using two memory buffers.
+///
+/// In double buffer mode the DMA controller alternates between two
+/// buffers of the same length. When one buffer is complete, the transfer
+/// complete flag is set and the controller continues with the other
+/// buffer. Meanwhile software can process the completed buffer with
+/// [`next_transfer_with`](#method.next_transfer_with), or exchange it for a
+/// new buffer with [`next_transfer`](#method.next_transfer).
+///
+/// If software is too slow and the DMA controller completes the other
+/// buffer before software has finished, these methods return
+/// `DmaError::Overrun`.
+pub struct DoubleBufferedTransfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: DoubleBufferedStream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    stream: STREAM,
+    peripheral: PERIPHERAL,
+    _direction: PhantomData<DIR>,
+    buf: [BUF; 2],
+    transfer_length: usize,
+}
+
+impl<STREAM, PERIPHERAL, BUF>
+    DoubleBufferedTransfer<STREAM, PERIPHERAL, MemoryToPeripheral, BUF>
+where
+    STREAM: DoubleBufferedStream,
+    PERIPHERAL: TargetAddress<MemoryToPeripheral, Mux = STREAM::Mux>,
+    BUF: StaticReadBuffer<
+        Word = <PERIPHERAL as TargetAddress<MemoryToPeripheral>>::MemSize,
+    >,
+{
+    /// Configures the DMA stream for a circular transfer that
+    /// alternates between `first_buf` and `second_buf`. The stream is not
+    /// enabled until `start` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffers have different lengths, or are longer than
+    /// 65535 words
+    pub fn init(
+        stream: STREAM,
+        peripheral: PERIPHERAL,
+        first_buf: BUF,
+        second_buf: BUF,
+        config: STREAM::Config,
+    ) -> Self {
+        let (first_ptr, first_len) = first_buf.static_read_buffer();
+        let (second_ptr, second_len) = second_buf.static_read_buffer();
+        assert_eq!(first_len, second_len, "Buffer lengths must be equal");
+
+        Self::init_common(
+            stream,
+            peripheral,
+            [first_buf, second_buf],
+            [first_ptr as usize, second_ptr as usize],
+            first_len,
+            config,
+        )
+    }
+
+    /// Replaces the buffer that was most recently completed by
+    /// `new_buf`, and returns the completed buffer.
+    ///
+    /// Returns `DmaError::NotReady` if no buffer has been completed since
+    /// the last call, and `DmaError::SmallBuffer` if `new_buf` is shorter
+    /// than the transfer length. In these cases `new_buf` is returned
+    /// alongside the error. Returns `DmaError::Overrun` alongside the
+    /// completed buffer if the DMA controller completed the other buffer
+    /// before the exchange took place.
+    pub fn next_transfer(
+        &mut self,
+        new_buf: BUF,
+    ) -> Result<BUF, (DmaError, BUF)> {
+        let (ptr, len) = new_buf.static_read_buffer();
+        self.replace_inactive(new_buf, ptr as usize, len)
+    }
+}
+
+impl<STREAM, PERIPHERAL, BUF>
+    DoubleBufferedTransfer<STREAM, PERIPHERAL, PeripheralToMemory, BUF>
+where
+    STREAM: DoubleBufferedStream,
+    PERIPHERAL: TargetAddress<PeripheralToMemory, Mux = STREAM::Mux>,
+    BUF: StaticWriteBuffer<
+        Word = <PERIPHERAL as TargetAddress<PeripheralToMemory>>::MemSize,
+    >,
+{
+    /// Configures the DMA stream for a circular transfer that
+    /// alternates between `first_buf` and `second_buf`. The stream is not
+    /// enabled until `start` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffers have different lengths, or are longer than
+    /// 65535 words
+    pub fn init(
+        stream: STREAM,
+        peripheral: PERIPHERAL,
+        mut first_buf: BUF,
+        mut second_buf: BUF,
+        config: STREAM::Config,
+    ) -> Self {
+        let (first_ptr, first_len) = first_buf.static_write_buffer();
+        let (second_ptr, second_len) = second_buf.static_write_buffer();
+        assert_eq!(first_len, second_len, "Buffer lengths must be equal");
+
+        Self::init_common(
+            stream,
+            peripheral,
+            [first_buf, second_buf],
+            [first_ptr as usize, second_ptr as usize],
+            first_len,
+            config,
+        )
+    }
+
+    /// Replaces the buffer that was most recently completed by
+    /// `new_buf`, and returns the completed buffer.
+    ///
+    /// Returns `DmaError::NotReady` if no buffer has been completed since
+    /// the last call, and `DmaError::SmallBuffer` if `new_buf` is shorter
+    /// than the transfer length. In these cases `new_buf` is returned
+    /// alongside the error. Returns `DmaError::Overrun` alongside the
+    /// completed buffer if the DMA controller completed the other buffer
+    /// before the exchange took place.
+    pub fn next_transfer(
+        &mut self,
+        mut new_buf: BUF,
+    ) -> Result<BUF, (DmaError, BUF)> {
+        let (ptr, len) = new_buf.static_write_buffer();
+        self.replace_inactive(new_buf, ptr as usize, len)
+    }
+}
+
+impl<STREAM, PERIPHERAL, DIR, BUF>
+    DoubleBufferedTransfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: DoubleBufferedStream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    fn init_common(
+        mut stream: STREAM,
+        peripheral: PERIPHERAL,
+        buf: [BUF; 2],
+        buf_addr: [usize; 2],
+        buf_len: usize,
+        config: STREAM::Config,
+    ) -> Self {
+        configure_stream::<_, _, DIR>(
+            &mut stream,
+            &peripheral,
+            buf_addr[0],
+            buf_len,
+            config,
+        );
+        stream.set_memory_double_buffer_address(buf_addr[1]);
+        stream.set_double_buffer(true);
+
+        DoubleBufferedTransfer {
+            stream,
+            peripheral,
+            _direction: PhantomData,
+            buf,
+            transfer_length: buf_len,
+        }
+    }
+
+    /// Starts the transfer. The closure is called after the stream is
+    /// enabled, and should enable DMA requests in the peripheral.
+    pub fn start<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut PERIPHERAL),
+    {
+        // "Preceding reads and writes cannot be moved past subsequent
+        // writes"
+        compiler_fence(Ordering::Release);
+
+        unsafe {
+            self.stream.enable();
+        }
+        f(&mut self.peripheral);
+    }
+
+    /// Returns the buffer that the DMA controller is currently using
+    pub fn current_buffer(&self) -> CurrentBuffer {
+        STREAM::get_current_buffer()
+    }
+
+    /// Calls `f` with the buffer that was most recently completed by the
+    /// DMA controller, and which of the two buffers it is.
+    ///
+    /// Returns `DmaError::NotReady` if no buffer has been completed since
+    /// the last call. Returns `DmaError::Overrun` if the DMA controller
+    /// completed the other buffer while `f` was running. In that case the
+    /// DMA controller may have accessed the buffer at the same time as
+    /// `f`, and its contents should be discarded.
+    pub fn next_transfer_with<F, T>(&mut self, f: F) -> Result<T, DmaError>
+    where
+        F: FnOnce(&mut BUF, CurrentBuffer) -> T,
+    {
+        let completed = self.take_completed()?;
+        if STREAM::get_current_buffer() == completed {
+            // Already switched back to the completed buffer
+            return Err(DmaError::Overrun);
+        }
+
+        // "No re-ordering of reads and writes across this point is
+        // allowed"
+        compiler_fence(Ordering::SeqCst);
+
+        let index = match completed {
+            CurrentBuffer::FirstBuffer => 0,
+            CurrentBuffer::SecondBuffer => 1,
+        };
+        let result = f(&mut self.buf[index], completed);
+
+        compiler_fence(Ordering::SeqCst);
+
+        if STREAM::get_transfer_complete_flag()
+            || STREAM::get_current_buffer() == completed
+        {
+            return Err(DmaError::Overrun);
+        }
+
+        Ok(result)
+    }
+
+    /// Clears the transfer complete flag, and returns the buffer that
+    /// was completed
+    fn take_completed(&mut self) -> Result<CurrentBuffer, DmaError> {
+        if !STREAM::get_transfer_complete_flag() {
+            return Err(DmaError::NotReady);
+        }
+        self.stream.clear_transfer_complete_interrupt();
+
+        Ok(STREAM::get_current_buffer().other())
+    }
+
+    fn replace_inactive(
+        &mut self,
+        new_buf: BUF,
+        buf_addr: usize,
+        buf_len: usize,
+    ) -> Result<BUF, (DmaError, BUF)> {
+        if buf_len < self.transfer_length {
+            return Err((DmaError::SmallBuffer, new_buf));
+        }
+        let completed = match self.take_completed() {
+            Ok(completed) => completed,
+            Err(e) => return Err((e, new_buf)),
+        };
+        if STREAM::get_current_buffer() == completed {
+            // Already switched back to the completed buffer
+            return Err((DmaError::Overrun, new_buf));
+        }
+
+        // "No re-ordering of reads and writes across this point is
+        // allowed"
+        compiler_fence(Ordering::SeqCst);
+
+        // The memory address of the buffer that is not in use can be
+        // written while the stream is enabled
+        let old_buf = match completed {
+            CurrentBuffer::FirstBuffer => {
+                self.stream.set_memory_address(buf_addr);
+                mem::replace(&mut self.buf[0], new_buf)
+            }
+            CurrentBuffer::SecondBuffer => {
+                self.stream.set_memory_double_buffer_address(buf_addr);
+                mem::replace(&mut self.buf[1], new_buf)
+            }
+        };
+
+        compiler_fence(Ordering::SeqCst);
+
+        if STREAM::get_transfer_complete_flag()
+            || STREAM::get_transfer_error_flag()
+            || STREAM::get_current_buffer() == completed
+        {
+            return Err((DmaError::Overrun, old_buf));
+        }
+
+        Ok(old_buf)
+    }
+
+    /// Returns true if the transfer complete flag is set
+    pub fn get_transfer_complete_flag(&self) -> bool {
+        STREAM::get_transfer_complete_flag()
+    }
+
+    /// Returns true if the half transfer flag is set
+    pub fn get_half_transfer_flag(&self) -> bool {
+        STREAM::get_half_transfer_flag()
+    }
+
+    /// Returns true if the transfer error flag is set
+    pub fn get_transfer_error_flag(&self) -> bool {
+        STREAM::get_transfer_error_flag()
+    }
+
+    /// Clears all the interrupt flags of the stream
+    pub fn clear_interrupts(&mut self) {
+        self.stream.clear_interrupts();
+    }
+
+    /// Clears the half transfer flag
+    pub fn clear_half_transfer_interrupt(&mut self) {
+        self.stream.clear_half_transfer_interrupt();
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        listen(&mut self.stream, event, true);
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        listen(&mut self.stream, event, false);
+    }
+
+    /// Stops the transfer, and returns the stream, the peripheral and
+    /// both buffers
+    pub fn free(mut self) -> (STREAM, PERIPHERAL, BUF, BUF) {
+        self.stream.disable();
+        compiler_fence(Ordering::SeqCst);
+        self.stream.clear_interrupts();
+        self.stream.set_double_buffer(false);
+
+        unsafe {
+            let stream = ptr::read(&self.stream);
+            let peripheral = ptr::read(&self.peripheral);
+            let [first, second] = ptr::read(&self.buf);
+            mem::forget(self);
+            (stream, peripheral, first, second)
+        }
+    }
+}
+
+impl<STREAM, PERIPHERAL, DIR, BUF> Drop
+    for DoubleBufferedTransfer<STREAM, PERIPHERAL, DIR, BUF>
+where
+    STREAM: DoubleBufferedStream,
+    PERIPHERAL: TargetAddress<DIR, Mux = STREAM::Mux>,
+    DIR: Direction,
+{
+    fn drop(&mut self) {
+        self.stream.disable();
+        compiler_fence(Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,152 @@
+//! Traits for DMA streams and the peripherals that they can serve
+
+use super::{CurrentBuffer, DmaDirection};
+
+pub(crate) mod sealed {
+    /// Prevents traits from being implemented outside this crate
+    pub trait Sealed {}
+}
+use sealed::Sealed;
+
+/// Marker trait for the direction of a transfer
+pub trait Direction {
+    /// Returns the direction as a runtime value
+    fn direction() -> DmaDirection;
+}
+
+/// Trait for DMA streams
+pub trait Stream: Sealed {
+    /// Configuration structure for this stream
+    type Config;
+
+    /// The DMA request multiplexer that serves this stream, `DMAMUX1` or
+    /// `DMAMUX2`
+    type Mux;
+
+    /// Apply the configuration structure to this stream
+    fn apply_config(&mut self, config: Self::Config);
+
+    /// Clear all interrupts for the DMA stream
+    fn clear_interrupts(&mut self);
+
+    /// Clear transfer complete interrupt (tcif) for the DMA stream
+    fn clear_transfer_complete_interrupt(&mut self);
+
+    /// Clear half transfer interrupt (htif) for the DMA stream
+    fn clear_half_transfer_interrupt(&mut self);
+
+    /// Clear transfer error interrupt (teif) for the DMA stream
+    fn clear_transfer_error_interrupt(&mut self);
+
+    /// Get transfer complete flag
+    fn get_transfer_complete_flag() -> bool;
+
+    /// Get half transfer flag
+    fn get_half_transfer_flag() -> bool;
+
+    /// Get transfer error flag
+    fn get_transfer_error_flag() -> bool;
+
+    /// Set the peripheral address (par) for the DMA stream
+    fn set_peripheral_address(&mut self, value: usize);
+
+    /// Set the memory address (m0ar) for the DMA stream
+    fn set_memory_address(&mut self, value: usize);
+
+    /// Set the number of transfers (ndt) for the DMA stream
+    fn set_number_of_transfers(&mut self, value: u16);
+
+    /// Get the number of transfers (ndt) for the DMA stream
+    fn get_number_of_transfers() -> u16;
+
+    /// Set the memory word size. `size` is encoded as for the MSIZE field
+    ///
+    /// # Safety
+    ///
+    /// Must be 0b00, 0b01 or 0b10
+    unsafe fn set_memory_size(&mut self, size: u8);
+
+    /// Set the peripheral word size. `size` is encoded as for the PSIZE
+    /// field
+    ///
+    /// # Safety
+    ///
+    /// Must be 0b00, 0b01 or 0b10
+    unsafe fn set_peripheral_size(&mut self, size: u8);
+
+    /// Set the direction (dir) of the DMA stream
+    fn set_direction(&mut self, direction: DmaDirection);
+
+    /// Set the DMAMUX request line for this stream
+    fn set_request_line(&mut self, request_line: u8);
+
+    /// Enable the DMA stream
+    ///
+    /// # Safety
+    ///
+    /// The user must ensure that all registers are properly configured
+    unsafe fn enable(&mut self);
+
+    /// Returns the state of the DMA stream
+    fn is_enabled() -> bool;
+
+    /// Disable the DMA stream.
+    ///
+    /// Blocks until the stream is actually disabled. Disabling the stream
+    /// during an ongoing transfer causes the transfer complete flag to be
+    /// set.
+    fn disable(&mut self);
+
+    /// Enable or disable the transfer complete interrupt (tcie)
+    fn set_transfer_complete_interrupt_enable(&mut self, enable: bool);
+
+    /// Enable or disable the half transfer interrupt (htie)
+    fn set_half_transfer_interrupt_enable(&mut self, enable: bool);
+
+    /// Enable or disable the transfer error interrupt (teie)
+    fn set_transfer_error_interrupt_enable(&mut self, enable: bool);
+}
+
+/// Trait for DMA streams that support double buffer mode
+pub trait DoubleBufferedStream: Stream {
+    /// Set the memory address of the second buffer (m1ar)
+    fn set_memory_double_buffer_address(&mut self, value: usize);
+
+    /// Enable or disable double buffer mode (dbm)
+    fn set_double_buffer(&mut self, double_buffer: bool);
+
+    /// Returns the buffer that the DMA controller is currently using (ct)
+    fn get_current_buffer() -> CurrentBuffer;
+}
+
+/// Marker trait for streams whose DMA controller can access the memory of
+/// buffers of type `BUF`
+///
+/// # Safety
+///
+/// The DMA controller serving this stream must be able to read and write
+/// any buffer of type `BUF`.
+pub unsafe trait MemoryAccess<BUF> {}
+
+/// Trait for peripheral addresses that can be the source or destination
+/// of a DMA transfer
+///
+/// # Safety
+///
+/// The address returned by `address` must be a valid register or memory
+/// location for transfers of `MemSize` words in direction `D`.
+pub unsafe trait TargetAddress<D: Direction> {
+    /// Size of each word transferred to or from the target address
+    type MemSize;
+
+    /// The DMA request multiplexer that routes `REQUEST_LINE`, `DMAMUX1`
+    /// or `DMAMUX2`. The target can only be used with streams served by
+    /// the same multiplexer
+    type Mux;
+
+    /// The request line used by this target on `Mux`, if any
+    const REQUEST_LINE: Option<u8> = None;
+
+    /// The address to be used by the DMA stream
+    fn address(&self) -> usize;
+}
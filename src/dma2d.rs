@@ -0,0 +1,662 @@
+//! Chrom-ART Accelerator (DMA2D)
+//!
+//! The DMA2D is a DMA controller dedicated to graphics. It can fill a
+//! rectangle with a colour, copy a rectangle between images with different
+//! line widths, convert between pixel formats and blend a foreground image
+//! over a background image.
+//!
+//! # Usage
+//!
+//! ```
+//! use stm32h7xx_hal::dma2d::{Dma2dExt, Layer, PixelFormat};
+//!
+//! let dma2d = dp.DMA2D.dma2d(ccdr.peripheral.DMA2D);
+//!
+//! // Fill a 100x50 rectangle at (10, 20) of a 480 pixel wide framebuffer
+//! let layer = Layer::new(PixelFormat::Rgb565, 480).position(10, 20);
+//! let transfer = dma2d.fill((framebuffer, layer), (100, 50), 0xF800);
+//!
+//! while !transfer.get_transfer_complete_flag() {}
+//! let (dma2d, framebuffer, _, _) = transfer.free();
+//! ```
+//!
+//! Transfers can also signal their completion with an interrupt, see
+//! [`Dma2d::listen`](struct.Dma2d.html#method.listen).
+//!
+//! Indexed pixel formats (`L8`, `L4`, `AL44`) are converted to colours
+//! using a colour look-up table (CLUT), which is loaded with
+//! [`load_clut`](struct.Dma2d.html#method.load_clut) before the transfer.
+//! Pixels in the alpha-only formats (`A8`, `A4`) take their colour from
+//! the layer, see [`Layer::color`](struct.Layer.html#method.color).
+//!
+//! The DMA2D is an AXI master in the D1 domain, and cannot access the DTCM
+//! or ITCM. Buffers are not checked for this.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::dma::{StaticReadBuffer, StaticWriteBuffer};
+use crate::rcc::{rec, ResetEnable};
+use crate::stm32::DMA2D;
+
+/// Pixel format of an image.
+///
+/// All formats can be used for input. Only `Argb8888`, `Rgb888`, `Rgb565`,
+/// `Argb1555` and `Argb4444` can be used for output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    /// 32-bit ARGB
+    Argb8888,
+    /// 24-bit RGB
+    Rgb888,
+    /// 16-bit RGB
+    Rgb565,
+    /// 16-bit ARGB, 1-bit alpha
+    Argb1555,
+    /// 16-bit ARGB, 4-bit alpha
+    Argb4444,
+    /// 8-bit index into the CLUT
+    L8,
+    /// 4-bit alpha and 4-bit index into the CLUT
+    Al44,
+    /// 8-bit alpha and 8-bit index into the CLUT
+    Al88,
+    /// 4-bit index into the CLUT
+    L4,
+    /// 8-bit alpha, colour from the layer colour register
+    A8,
+    /// 4-bit alpha, colour from the layer colour register
+    A4,
+}
+
+impl PixelFormat {
+    /// Value of the CM field
+    fn bits(self) -> u8 {
+        match self {
+            PixelFormat::Argb8888 => 0b0000,
+            PixelFormat::Rgb888 => 0b0001,
+            PixelFormat::Rgb565 => 0b0010,
+            PixelFormat::Argb1555 => 0b0011,
+            PixelFormat::Argb4444 => 0b0100,
+            PixelFormat::L8 => 0b0101,
+            PixelFormat::Al44 => 0b0110,
+            PixelFormat::Al88 => 0b0111,
+            PixelFormat::L4 => 0b1000,
+            PixelFormat::A8 => 0b1001,
+            PixelFormat::A4 => 0b1010,
+        }
+    }
+
+    /// Number of bits per pixel
+    pub fn bits_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Argb8888 => 32,
+            PixelFormat::Rgb888 => 24,
+            PixelFormat::Rgb565
+            | PixelFormat::Argb1555
+            | PixelFormat::Argb4444
+            | PixelFormat::Al88 => 16,
+            PixelFormat::L8 | PixelFormat::Al44 | PixelFormat::A8 => 8,
+            PixelFormat::L4 | PixelFormat::A4 => 4,
+        }
+    }
+
+    /// Returns true if the format can be used for output
+    fn is_output(self) -> bool {
+        self.bits() <= PixelFormat::Argb4444.bits()
+    }
+}
+
+/// Alpha value applied to the pixels of an input layer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alpha {
+    /// The alpha value of each pixel is used
+    Unchanged,
+    /// The alpha value of each pixel is replaced by this value
+    Replace(u8),
+    /// The alpha value of each pixel is multiplied by this value / 255
+    Multiply(u8),
+}
+
+/// Placement of a rectangle within an image in memory.
+///
+/// This structure uses builder semantics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layer {
+    format: PixelFormat,
+    line_width: u16,
+    x: u16,
+    y: u16,
+    alpha: Alpha,
+    color: u32,
+}
+
+impl Layer {
+    /// An image of `format` pixels, with `line_width` pixels on each line
+    /// of the image. The rectangle starts at the top left corner of the
+    /// image, and the alpha values of the pixels are used unchanged.
+    pub fn new(format: PixelFormat, line_width: u16) -> Self {
+        Layer {
+            format,
+            line_width,
+            x: 0,
+            y: 0,
+            alpha: Alpha::Unchanged,
+            color: 0,
+        }
+    }
+
+    /// Set the position of the top left corner of the rectangle within
+    /// the image
+    pub fn position(mut self, x: u16, y: u16) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Set the alpha value applied to the pixels of this layer. Only used
+    /// for input layers
+    pub fn alpha(mut self, alpha: Alpha) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Set the colour of the pixels of this layer, as 0xRRGGBB. Only used
+    /// for input layers in the `A8` and `A4` formats, whose pixels only
+    /// hold an alpha value. Defaults to black
+    pub fn color(mut self, color: u32) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Red, green and blue components of the layer colour
+    fn color_components(&self) -> (u8, u8, u8) {
+        (
+            (self.color >> 16) as u8,
+            (self.color >> 8) as u8,
+            self.color as u8,
+        )
+    }
+
+    /// Returns the address of the first pixel and the line offset for a
+    /// rectangle of `width` x `height` pixels in a buffer
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rectangle is empty, or does not fit in the image or
+    /// the buffer
+    fn address(
+        &self,
+        buffer: usize,
+        buffer_bytes: usize,
+        width: u16,
+        height: u16,
+    ) -> (u32, u16) {
+        assert!(width > 0 && height > 0, "Rectangle is empty");
+        assert!(
+            self.x as usize + width as usize <= self.line_width as usize,
+            "Rectangle is wider than the image"
+        );
+
+        let bits = self.format.bits_per_pixel();
+        let line = self.line_width as usize;
+        let first = (self.y as usize * line + self.x as usize) * bits;
+        let end = ((self.y as usize + height as usize - 1) * line
+            + self.x as usize
+            + width as usize)
+            * bits;
+        assert!(first & 7 == 0, "Rectangle must start on a byte boundary");
+        assert!(end <= buffer_bytes * 8, "Buffer too small");
+
+        ((buffer + first / 8) as u32, self.line_width - width)
+    }
+}
+
+/// DMA2D interrupt events
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A transfer completed
+    TransferComplete,
+    /// A bus error occurred during a transfer
+    TransferError,
+    /// A transfer was started with an invalid configuration
+    ConfigurationError,
+}
+
+/// Selects the CLUT of the foreground or background layer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clut {
+    Foreground,
+    Background,
+}
+
+/// DMA2D errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// A bus error occurred
+    Transfer,
+    /// The configuration was invalid
+    Configuration,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+pub trait Dma2dExt {
+    fn dma2d(self, prec: rec::Dma2d) -> Dma2d;
+}
+
+impl Dma2dExt for DMA2D {
+    fn dma2d(self, prec: rec::Dma2d) -> Dma2d {
+        prec.enable().reset();
+
+        Dma2d { rb: self }
+    }
+}
+
+/// Chrom-ART Accelerator
+pub struct Dma2d {
+    rb: DMA2D,
+}
+
+// Length of a buffer in bytes
+fn byte_len<W>(len: usize) -> usize {
+    len * mem::size_of::<W>()
+}
+
+impl Dma2d {
+    /// Fills a rectangle of `size` (width, height) pixels in `out` with
+    /// `color`. The colour is given in the pixel format of `out`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format of `out` cannot be used for output, or if the
+    /// rectangle does not fit in the buffer
+    pub fn fill<OUT>(
+        self,
+        out: (OUT, Layer),
+        size: (u16, u16),
+        color: u32,
+    ) -> Dma2dTransfer<OUT, (), ()>
+    where
+        OUT: StaticWriteBuffer,
+    {
+        let (mut out, out_layer) = out;
+        self.set_output(&mut out, &out_layer, size);
+
+        // unsafe: the colour register is interpreted in the output format
+        self.rb.ocolr.write(|w| unsafe { w.bits(color) });
+
+        self.start(0b11, size, out, (), ())
+    }
+
+    /// Copies a rectangle of `size` (width, height) pixels from `source` to
+    /// `out`. The pixel format is converted if the formats of the layers
+    /// differ, and the alpha setting of the source layer is applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format of `out` cannot be used for output, or if the
+    /// rectangle does not fit in the buffers
+    pub fn copy<SRC, OUT>(
+        self,
+        source: (SRC, Layer),
+        out: (OUT, Layer),
+        size: (u16, u16),
+    ) -> Dma2dTransfer<OUT, SRC, ()>
+    where
+        SRC: StaticReadBuffer,
+        OUT: StaticWriteBuffer,
+    {
+        let (source, source_layer) = source;
+        let (mut out, out_layer) = out;
+        self.set_output(&mut out, &out_layer, size);
+        self.set_foreground(&source, &source_layer, size);
+
+        // Without conversion, the pixel format of the output is used
+        let mode = if source_layer.format == out_layer.format
+            && source_layer.alpha == Alpha::Unchanged
+        {
+            0b00
+        } else {
+            0b01
+        };
+
+        self.start(mode, size, out, source, ())
+    }
+
+    /// Blends a rectangle of `size` (width, height) pixels from
+    /// `foreground` over `background`, and writes the result to `out`.
+    /// `out` may be the same memory as `background`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format of `out` cannot be used for output, or if the
+    /// rectangle does not fit in the buffers
+    pub fn blend<FG, BG, OUT>(
+        self,
+        foreground: (FG, Layer),
+        background: (BG, Layer),
+        out: (OUT, Layer),
+        size: (u16, u16),
+    ) -> Dma2dTransfer<OUT, FG, BG>
+    where
+        FG: StaticReadBuffer,
+        BG: StaticReadBuffer,
+        OUT: StaticWriteBuffer,
+    {
+        let (foreground, foreground_layer) = foreground;
+        let (background, background_layer) = background;
+        let (mut out, out_layer) = out;
+        self.set_output(&mut out, &out_layer, size);
+        self.set_foreground(&foreground, &foreground_layer, size);
+
+        let (bg_ptr, bg_len) = background.static_read_buffer();
+        let (address, offset) = background_layer.address(
+            bg_ptr as usize,
+            byte_len::<BG::Word>(bg_len),
+            size.0,
+            size.1,
+        );
+        let (am, alpha) = Self::alpha_bits(background_layer.alpha);
+        let (red, green, blue) = background_layer.color_components();
+        self.rb.bgmar.write(|w| unsafe { w.ma().bits(address) });
+        self.rb.bgor.write(|w| w.lo().bits(offset));
+        self.rb
+            .bgcolr
+            .write(|w| w.red().bits(red).green().bits(green).blue().bits(blue));
+        self.rb.bgpfccr.write(|w| unsafe {
+            w.cm()
+                .bits(background_layer.format.bits())
+                .am()
+                .bits(am)
+                .alpha()
+                .bits(alpha)
+        });
+
+        self.start(0b10, size, out, foreground, background)
+    }
+
+    /// Loads a CLUT of 32-bit ARGB colours for indexed pixel formats. The
+    /// CLUT remains loaded for following transfers. Blocks until the CLUT
+    /// is loaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `colors` is empty or has more than 256 entries
+    pub fn load_clut(
+        &mut self,
+        clut: Clut,
+        colors: &[u32],
+    ) -> Result<(), Error> {
+        assert!(!colors.is_empty() && colors.len() <= 256);
+
+        let address = colors.as_ptr() as u32;
+        let size = (colors.len() - 1) as u8;
+
+        self.clear_interrupts();
+        compiler_fence(Ordering::Release);
+
+        // CLUT in ARGB8888 format, loaded immediately
+        match clut {
+            Clut::Foreground => {
+                self.rb.fgcmar.write(|w| unsafe { w.ma().bits(address) });
+                self.rb.fgpfccr.write(|w| unsafe {
+                    w.cm().bits(PixelFormat::L8.bits()).cs().bits(size)
+                });
+                self.rb.fgpfccr.modify(|_, w| w.start().set_bit());
+            }
+            Clut::Background => {
+                self.rb.bgcmar.write(|w| unsafe { w.ma().bits(address) });
+                self.rb.bgpfccr.write(|w| unsafe {
+                    w.cm().bits(PixelFormat::L8.bits()).cs().bits(size)
+                });
+                self.rb.bgpfccr.modify(|_, w| w.start().set_bit());
+            }
+        }
+
+        loop {
+            let isr = self.rb.isr.read();
+            if isr.caeif().bit_is_set() {
+                self.clear_interrupts();
+                return Err(Error::Transfer);
+            }
+            if isr.ceif().bit_is_set() {
+                self.clear_interrupts();
+                return Err(Error::Configuration);
+            }
+            if isr.ctcif().bit_is_set() {
+                self.clear_interrupts();
+                return Ok(());
+            }
+        }
+    }
+
+    /// Start listening for `event`
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::TransferComplete => {
+                self.rb.cr.modify(|_, w| w.tcie().set_bit())
+            }
+            Event::TransferError => self
+                .rb
+                .cr
+                .modify(|_, w| w.teie().set_bit().caeie().set_bit()),
+            Event::ConfigurationError => {
+                self.rb.cr.modify(|_, w| w.ceie().set_bit())
+            }
+        }
+    }
+
+    /// Stop listening for `event`
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::TransferComplete => {
+                self.rb.cr.modify(|_, w| w.tcie().clear_bit())
+            }
+            Event::TransferError => self
+                .rb
+                .cr
+                .modify(|_, w| w.teie().clear_bit().caeie().clear_bit()),
+            Event::ConfigurationError => {
+                self.rb.cr.modify(|_, w| w.ceie().clear_bit())
+            }
+        }
+    }
+
+    /// Clears all interrupt flags
+    pub fn clear_interrupts(&mut self) {
+        self.rb.ifcr.write(|w| {
+            w.ctcif()
+                .set_bit()
+                .cteif()
+                .set_bit()
+                .cceif()
+                .set_bit()
+                .cctcif()
+                .set_bit()
+                .caecif()
+                .set_bit()
+                .ctwif()
+                .set_bit()
+        });
+    }
+
+    /// Releases the DMA2D peripheral
+    pub fn free(self) -> (DMA2D, rec::Dma2d) {
+        (
+            self.rb,
+            rec::Dma2d {
+                _marker: PhantomData,
+            },
+        )
+    }
+
+    /// AM and ALPHA fields for `alpha`
+    fn alpha_bits(alpha: Alpha) -> (u8, u8) {
+        match alpha {
+            Alpha::Unchanged => (0b00, 0xFF),
+            Alpha::Replace(alpha) => (0b01, alpha),
+            Alpha::Multiply(alpha) => (0b10, alpha),
+        }
+    }
+
+    fn set_output<OUT>(&self, out: &mut OUT, layer: &Layer, size: (u16, u16))
+    where
+        OUT: StaticWriteBuffer,
+    {
+        assert!(
+            layer.format.is_output(),
+            "Pixel format cannot be used for output"
+        );
+
+        let (ptr, len) = out.static_write_buffer();
+        let (address, offset) = layer.address(
+            ptr as usize,
+            byte_len::<OUT::Word>(len),
+            size.0,
+            size.1,
+        );
+        self.rb.omar.write(|w| unsafe { w.ma().bits(address) });
+        self.rb.oor.write(|w| w.lo().bits(offset));
+        self.rb
+            .opfccr
+            .write(|w| unsafe { w.cm().bits(layer.format.bits()) });
+    }
+
+    fn set_foreground<FG>(&self, fg: &FG, layer: &Layer, size: (u16, u16))
+    where
+        FG: StaticReadBuffer,
+    {
+        let (ptr, len) = fg.static_read_buffer();
+        let (address, offset) = layer.address(
+            ptr as usize,
+            byte_len::<FG::Word>(len),
+            size.0,
+            size.1,
+        );
+        let (am, alpha) = Self::alpha_bits(layer.alpha);
+        let (red, green, blue) = layer.color_components();
+        self.rb.fgmar.write(|w| unsafe { w.ma().bits(address) });
+        self.rb.fgor.write(|w| w.lo().bits(offset));
+        self.rb
+            .fgcolr
+            .write(|w| w.red().bits(red).green().bits(green).blue().bits(blue));
+        self.rb.fgpfccr.write(|w| unsafe {
+            w.cm()
+                .bits(layer.format.bits())
+                .am()
+                .bits(am)
+                .alpha()
+                .bits(alpha)
+        });
+    }
+
+    fn start<OUT, FG, BG>(
+        mut self,
+        mode: u8,
+        size: (u16, u16),
+        out: OUT,
+        fg: FG,
+        bg: BG,
+    ) -> Dma2dTransfer<OUT, FG, BG> {
+        assert!(size.0 > 0 && size.0 < (1 << 14), "Invalid width");
+        assert!(size.1 > 0, "Invalid height");
+
+        self.rb.nlr.write(|w| w.pl().bits(size.0).nl().bits(size.1));
+        self.clear_interrupts();
+
+        // "Preceding reads and writes cannot be moved past subsequent
+        // writes"
+        compiler_fence(Ordering::Release);
+
+        self.rb
+            .cr
+            .modify(|_, w| w.mode().bits(mode).start().set_bit());
+
+        Dma2dTransfer {
+            dma2d: self,
+            out,
+            fg,
+            bg,
+        }
+    }
+}
+
+/// DMA2D transfer.
+///
+/// The transfer owns the DMA2D peripheral and the buffers until it is
+/// freed.
+pub struct Dma2dTransfer<OUT, FG, BG> {
+    dma2d: Dma2d,
+    out: OUT,
+    fg: FG,
+    bg: BG,
+}
+
+impl<OUT, FG, BG> Dma2dTransfer<OUT, FG, BG> {
+    /// Returns true if the transfer has completed
+    pub fn get_transfer_complete_flag(&self) -> bool {
+        self.dma2d.rb.isr.read().tcif().bit_is_set()
+    }
+
+    /// Returns true if the transfer has stopped with an error
+    pub fn get_transfer_error_flag(&self) -> bool {
+        let isr = self.dma2d.rb.isr.read();
+        isr.teif().bit_is_set()
+            || isr.caeif().bit_is_set()
+            || isr.ceif().bit_is_set()
+    }
+
+    /// Returns the result of the transfer, or `WouldBlock` if it is still
+    /// in progress
+    pub fn status(&self) -> nb::Result<(), Error> {
+        let isr = self.dma2d.rb.isr.read();
+        if isr.teif().bit_is_set() || isr.caeif().bit_is_set() {
+            Err(nb::Error::Other(Error::Transfer))
+        } else if isr.ceif().bit_is_set() {
+            Err(nb::Error::Other(Error::Configuration))
+        } else if isr.tcif().bit_is_set() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Clears all interrupt flags
+    pub fn clear_interrupts(&mut self) {
+        self.dma2d.clear_interrupts();
+    }
+
+    /// Stops the transfer if it is still in progress, and returns the
+    /// DMA2D peripheral and the output, foreground and background buffers
+    pub fn free(mut self) -> (Dma2d, OUT, FG, BG) {
+        self.abort();
+        compiler_fence(Ordering::SeqCst);
+        self.dma2d.clear_interrupts();
+
+        unsafe {
+            let dma2d = ptr::read(&self.dma2d);
+            let out = ptr::read(&self.out);
+            let fg = ptr::read(&self.fg);
+            let bg = ptr::read(&self.bg);
+            mem::forget(self);
+            (dma2d, out, fg, bg)
+        }
+    }
+
+    fn abort(&mut self) {
+        let cr = &self.dma2d.rb.cr;
+        if cr.read().start().bit_is_set() {
+            cr.modify(|_, w| w.abort().set_bit());
+            while cr.read().start().bit_is_set() {}
+        }
+    }
+}
+
+impl<OUT, FG, BG> Drop for Dma2dTransfer<OUT, FG, BG> {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
@@ -1,8 +1,32 @@
 //! Inter Integrated Circuit (I2C)
+//!
+//! # Non-blocking transactions
+//!
+//! In addition to the blocking `embedded-hal` traits, a transaction can be
+//! started with `start_write`, `start_read` or `start_write_read` and then
+//! advanced by calling `on_interrupt` from both the event and error
+//! interrupts of the peripheral. `on_interrupt` returns a [`Status`] that
+//! reports when the transaction has completed or failed, so that several
+//! devices can be serviced in turn without blocking.
+//!
+//! The `start_*_dma` variants move the data with DMA requests instead of
+//! the TXIS / RXNE interrupts. The DMA transfers are initialised on the
+//! targets returned by `dma_targets`, and must be started before the
+//! transaction. The remaining events are still handled by `on_interrupt`.
+//!
+//! I2C1 - I2C3 are served by DMA1 / DMA2 streams through DMAMUX1. I2C4 is
+//! in the D3 domain and is only served by BDMA channels through DMAMUX2, so
+//! its buffers must be located in SRAM4. Initialising a transfer on a
+//! stream of the wrong controller is a compile error.
+//!
+//! Transfers longer than 255 bytes are split using NBYTES reload.
 
 use core::cmp;
 use core::marker::PhantomData;
 
+use crate::dma::{
+    bdma, dma, traits::TargetAddress, MemoryToPeripheral, PeripheralToMemory,
+};
 use crate::gpio::gpioa::PA8;
 use crate::gpio::gpiob::{PB10, PB11, PB6, PB7, PB8, PB9};
 use crate::gpio::gpioc::PC9;
@@ -12,6 +36,7 @@ use crate::gpio::gpioh::{PH11, PH12, PH4, PH5, PH7, PH8};
 use crate::gpio::{Alternate, AF4, AF6};
 use crate::hal::blocking::i2c::{Read, Write, WriteRead};
 use crate::rcc::{rec, CoreClocks, ResetEnable};
+use crate::stm32::{DMAMUX1, DMAMUX2};
 use crate::stm32::{I2C1, I2C2, I2C3, I2C4};
 use crate::time::Hertz;
 use cast::u16;
@@ -35,7 +60,7 @@ pub enum Event {
 }
 
 /// I2C error
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Error {
     /// Bus error
     Bus,
@@ -78,9 +103,70 @@ where
     }
 }
 
+/// Status of a non-blocking transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// No transaction has been started
+    Idle,
+    /// A transaction is in progress
+    Busy,
+    /// The last transaction completed successfully
+    Complete,
+    /// The last transaction failed
+    Failed(Error),
+}
+
+/// State of a non-blocking transaction
+#[derive(Debug)]
+struct Transaction {
+    addr: u8,
+    /// Bytes to write, unless the data is moved by DMA
+    write: &'static [u8],
+    /// Buffer to read into, unless the data is moved by DMA
+    read: Option<&'static mut [u8]>,
+    write_len: usize,
+    read_len: usize,
+    /// Number of bytes moved by the interrupt handler in this phase
+    index: usize,
+    /// Number of bytes in this phase that are not yet programmed in
+    /// NBYTES
+    remaining: usize,
+    reading: bool,
+    dma: bool,
+    status: Status,
+}
+
+impl Transaction {
+    fn idle() -> Self {
+        Transaction {
+            addr: 0,
+            write: &[],
+            read: None,
+            write_len: 0,
+            read_len: 0,
+            index: 0,
+            remaining: 0,
+            reading: false,
+            dma: false,
+            status: Status::Idle,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct I2c<I2C> {
     i2c: I2C,
+    transaction: Transaction,
+}
+
+/// DMA target for the transmit data register of an I2C peripheral
+pub struct I2cTx<I2C> {
+    _i2c: PhantomData<I2C>,
+}
+
+/// DMA target for the receive data register of an I2C peripheral
+pub struct I2cRx<I2C> {
+    _i2c: PhantomData<I2C>,
 }
 
 pub trait I2cExt<I2C>: Sized {
@@ -281,7 +367,7 @@ macro_rules! i2c {
                     // Enable the peripheral
                     i2c.cr1.write(|w| w.pe().set_bit());
 
-                    I2c { i2c }
+                    I2c { i2c, transaction: Transaction::idle() }
                 }
 
                 /// Start listening for `event`
@@ -328,6 +414,331 @@ macro_rules! i2c {
                 }
 
 
+                /// Starts a non-blocking write of `bytes` to the device at
+                /// `addr`.
+                ///
+                /// The transaction is driven by `on_interrupt`, which must
+                /// be called from both the event and error interrupts of
+                /// this peripheral. Returns `WouldBlock` if a transaction
+                /// is already in progress.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bytes` is empty
+                pub fn start_write(
+                    &mut self,
+                    addr: u8,
+                    bytes: &'static [u8],
+                ) -> nb::Result<(), Error> {
+                    self.start_transaction(addr, bytes, None, bytes.len(), 0, false)
+                }
+
+                /// Starts a non-blocking read from the device at `addr`
+                /// into `buffer`. See `start_write`.
+                ///
+                /// When the transaction is complete, the buffer can be
+                /// retrieved with `take_read_buffer`.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `buffer` is empty
+                pub fn start_read(
+                    &mut self,
+                    addr: u8,
+                    buffer: &'static mut [u8],
+                ) -> nb::Result<(), Error> {
+                    let len = buffer.len();
+                    self.start_transaction(addr, &[], Some(buffer), 0, len, false)
+                }
+
+                /// Starts a non-blocking write of `bytes` to the device at
+                /// `addr`, followed by a repeated START and a read into
+                /// `buffer`. See `start_write`.
+                ///
+                /// When the transaction is complete, the buffer can be
+                /// retrieved with `take_read_buffer`.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bytes` or `buffer` is empty
+                pub fn start_write_read(
+                    &mut self,
+                    addr: u8,
+                    bytes: &'static [u8],
+                    buffer: &'static mut [u8],
+                ) -> nb::Result<(), Error> {
+                    assert!(!bytes.is_empty());
+                    let len = buffer.len();
+                    self.start_transaction(addr, bytes, Some(buffer), bytes.len(), len, false)
+                }
+
+                /// Starts a non-blocking write of `len` bytes to the
+                /// device at `addr`, with the data supplied by a DMA
+                /// transfer to the `I2cTx` target.
+                ///
+                /// The DMA transfer must be started before this method is
+                /// called. The transaction is driven by `on_interrupt` as
+                /// for `start_write`. Returns `WouldBlock` if a
+                /// transaction is already in progress.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `len` is zero
+                pub fn start_write_dma(
+                    &mut self,
+                    addr: u8,
+                    len: usize,
+                ) -> nb::Result<(), Error> {
+                    self.start_transaction(addr, &[], None, len, 0, true)
+                }
+
+                /// Starts a non-blocking read of `len` bytes from the
+                /// device at `addr`, with the data received by a DMA
+                /// transfer from the `I2cRx` target. See
+                /// `start_write_dma`.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `len` is zero
+                pub fn start_read_dma(
+                    &mut self,
+                    addr: u8,
+                    len: usize,
+                ) -> nb::Result<(), Error> {
+                    self.start_transaction(addr, &[], None, 0, len, true)
+                }
+
+                /// Starts a non-blocking write of `write_len` bytes
+                /// followed by a read of `read_len` bytes, with both
+                /// phases moved by DMA. See `start_write_dma`.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `write_len` or `read_len` is zero
+                pub fn start_write_read_dma(
+                    &mut self,
+                    addr: u8,
+                    write_len: usize,
+                    read_len: usize,
+                ) -> nb::Result<(), Error> {
+                    assert!(write_len > 0);
+                    self.start_transaction(addr, &[], None, write_len, read_len, true)
+                }
+
+                fn start_transaction(
+                    &mut self,
+                    addr: u8,
+                    write: &'static [u8],
+                    read: Option<&'static mut [u8]>,
+                    write_len: usize,
+                    read_len: usize,
+                    dma: bool,
+                ) -> nb::Result<(), Error> {
+                    if self.transaction.status == Status::Busy {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    assert!(write_len > 0 || read_len > 0);
+
+                    // Wait for any previous address sequence to end
+                    // automatically
+                    while self.i2c.cr2.read().start().bit_is_set() {};
+
+                    self.transaction = Transaction {
+                        addr,
+                        write,
+                        read,
+                        write_len,
+                        read_len,
+                        index: 0,
+                        remaining: 0,
+                        reading: write_len == 0,
+                        dma,
+                        status: Status::Busy,
+                    };
+
+                    self.i2c.icr.write(|w| {
+                        w.stopcf()
+                            .set_bit()
+                            .nackcf()
+                            .set_bit()
+                            .berrcf()
+                            .set_bit()
+                            .arlocf()
+                            .set_bit()
+                    });
+
+                    // Data is moved either by the TXIS/RXNE interrupts or
+                    // by DMA requests
+                    self.i2c.cr1.modify(|_, w| {
+                        w.tcie()
+                            .set_bit()
+                            .stopie()
+                            .set_bit()
+                            .nackie()
+                            .set_bit()
+                            .errie()
+                            .set_bit()
+                            .txie()
+                            .bit(!dma && write_len > 0)
+                            .rxie()
+                            .bit(!dma && read_len > 0)
+                            .txdmaen()
+                            .bit(dma && write_len > 0)
+                            .rxdmaen()
+                            .bit(dma && read_len > 0)
+                    });
+
+                    self.start_phase();
+
+                    Ok(())
+                }
+
+                /// Generates a START or repeated START for the current
+                /// phase of the transaction
+                fn start_phase(&mut self) {
+                    let t = &mut self.transaction;
+                    let len = if t.reading { t.read_len } else { t.write_len };
+
+                    // Transfers of more than 255 bytes use NBYTES reload
+                    let nbytes = cmp::min(len, 255);
+                    t.index = 0;
+                    t.remaining = len - nbytes;
+
+                    // STOP automatically after the last phase
+                    let autoend = t.reading || t.read_len == 0;
+                    let reload = t.remaining > 0;
+                    let (addr, reading) = (t.addr, t.reading);
+
+                    self.i2c.cr2.write(|w| {
+                        w.sadd()
+                            .bits(u16(addr << 1))
+                            .add10()
+                            .clear_bit()
+                            .rd_wrn()
+                            .bit(reading)
+                            .nbytes()
+                            .bits(nbytes as u8)
+                            .reload()
+                            .bit(reload)
+                            .autoend()
+                            .bit(autoend)
+                            .start()
+                            .set_bit()
+                    });
+                }
+
+                /// Ends the current transaction with `status`
+                fn end_transaction(&mut self, status: Status) -> Status {
+                    self.i2c.cr1.modify(|_, w| {
+                        w.tcie()
+                            .clear_bit()
+                            .stopie()
+                            .clear_bit()
+                            .nackie()
+                            .clear_bit()
+                            .errie()
+                            .clear_bit()
+                            .txie()
+                            .clear_bit()
+                            .rxie()
+                            .clear_bit()
+                            .txdmaen()
+                            .clear_bit()
+                            .rxdmaen()
+                            .clear_bit()
+                    });
+                    self.transaction.status = status;
+                    status
+                }
+
+                /// Advances the non-blocking transaction. This method
+                /// must be called from both the event and error
+                /// interrupts of this peripheral. Returns the status of
+                /// the transaction.
+                pub fn on_interrupt(&mut self) -> Status {
+                    if self.transaction.status != Status::Busy {
+                        return self.transaction.status;
+                    }
+
+                    let isr = self.i2c.isr.read();
+
+                    if isr.berr().is_error() {
+                        self.i2c.icr.write(|w| w.berrcf().set_bit());
+                        return self.end_transaction(Status::Failed(Error::Bus));
+                    }
+                    if isr.arlo().is_lost() {
+                        self.i2c.icr.write(|w| w.arlocf().set_bit());
+                        return self.end_transaction(Status::Failed(Error::Arbitration));
+                    }
+                    if isr.nackf().bit_is_set() {
+                        // A STOP is generated automatically after a NACK
+                        // in master mode
+                        self.i2c.icr.write(|w| w.nackcf().set_bit());
+                        flush_txdr!(self.i2c);
+                        return self.end_transaction(Status::Failed(Error::NotAcknowledge));
+                    }
+
+                    let t = &mut self.transaction;
+                    if !t.dma {
+                        if isr.txis().is_empty() && t.index < t.write.len() {
+                            self.i2c.txdr.write(|w| w.txdata().bits(t.write[t.index]));
+                            t.index += 1;
+                        }
+                        if isr.rxne().is_not_empty() {
+                            let byte = self.i2c.rxdr.read().rxdata().bits();
+                            if let Some(buffer) = t.read.as_mut() {
+                                if t.index < buffer.len() {
+                                    buffer[t.index] = byte;
+                                    t.index += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    if isr.tcr().is_complete() {
+                        // Reload NBYTES with the next part of this phase
+                        let nbytes = cmp::min(t.remaining, 255);
+                        t.remaining -= nbytes;
+                        let reload = t.remaining > 0;
+                        self.i2c.cr2.modify(|_, w| {
+                            w.nbytes().bits(nbytes as u8).reload().bit(reload)
+                        });
+                    } else if isr.tc().is_complete() {
+                        // The write phase is complete, and a read phase
+                        // follows
+                        t.reading = true;
+                        self.start_phase();
+                    }
+
+                    if isr.stopf().is_stop() {
+                        self.i2c.icr.write(|w| w.stopcf().set_bit());
+                        return self.end_transaction(Status::Complete);
+                    }
+
+                    Status::Busy
+                }
+
+                /// Returns the status of the non-blocking transaction
+                pub fn status(&self) -> Status {
+                    self.transaction.status
+                }
+
+                /// Returns the read buffer of the last non-blocking
+                /// transaction, if it is no longer in progress
+                pub fn take_read_buffer(&mut self) -> Option<&'static mut [u8]> {
+                    if self.transaction.status == Status::Busy {
+                        None
+                    } else {
+                        self.transaction.read.take()
+                    }
+                }
+
+                /// Returns the DMA targets for the transmit and receive
+                /// data registers of this peripheral
+                pub fn dma_targets(&self) -> (I2cTx<$I2CX>, I2cRx<$I2CX>) {
+                    (I2cTx { _i2c: PhantomData }, I2cRx { _i2c: PhantomData })
+                }
+
                 /// Releases the I2C peripheral
                 pub fn free(self) -> ($I2CX, rec::$Rec) {
                     (self.i2c, rec::$Rec { _marker: PhantomData })
@@ -556,6 +967,36 @@ macro_rules! i2c {
     };
 }
 
+macro_rules! i2c_dma {
+    ($($I2CX:ident: $Mux:ident ($rx_req:path, $tx_req:path),)+) => {
+        $(
+            unsafe impl TargetAddress<MemoryToPeripheral> for I2cTx<$I2CX> {
+                type MemSize = u8;
+                type Mux = $Mux;
+
+                const REQUEST_LINE: Option<u8> = Some($tx_req as u8);
+
+                fn address(&self) -> usize {
+                    // unsafe: only the address is taken
+                    unsafe { &(*$I2CX::ptr()).txdr as *const _ as usize }
+                }
+            }
+
+            unsafe impl TargetAddress<PeripheralToMemory> for I2cRx<$I2CX> {
+                type MemSize = u8;
+                type Mux = $Mux;
+
+                const REQUEST_LINE: Option<u8> = Some($rx_req as u8);
+
+                fn address(&self) -> usize {
+                    // unsafe: only the address is taken
+                    unsafe { &(*$I2CX::ptr()).rxdr as *const _ as usize }
+                }
+            }
+        )+
+    };
+}
+
 macro_rules! pins {
     ($($I2CX:ty: SCL: [$($SCL:ty),*] SDA: [$($SDA:ty),*])+) => {
         $(
@@ -637,3 +1078,10 @@ i2c!(
     I2C3: (i2c3, I2c3, pclk1),
     I2C4: (i2c4, I2c4, pclk4),
 );
+
+i2c_dma!(
+    I2C1: DMAMUX1 (dma::DMAReq::I2C1_RX_DMA, dma::DMAReq::I2C1_TX_DMA),
+    I2C2: DMAMUX1 (dma::DMAReq::I2C2_RX_DMA, dma::DMAReq::I2C2_TX_DMA),
+    I2C3: DMAMUX1 (dma::DMAReq::I2C3_RX_DMA, dma::DMAReq::I2C3_TX_DMA),
+    I2C4: DMAMUX2 (bdma::DMAReq::I2C4_RX_DMA, bdma::DMAReq::I2C4_TX_DMA),
+);
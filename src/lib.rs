@@ -31,6 +31,12 @@
 //! * [Timers](crate::timer)
 //! * [Delays](crate::delay)
 //!
+//! Direct Memory Access
+//!
+//! * [Direct Memory Access (DMA)](crate::dma)
+//! * [Master Direct Memory Access (MDMA)](crate::dma::mdma)
+//! * [Chrom-ART Accelerator (DMA2D)](crate::dma2d)
+//!
 //! Others
 //!
 //! * [Random Number Generator](crate::rng)
@@ -114,6 +120,10 @@ pub mod adc;
 pub mod dac;
 #[cfg(feature = "device-selected")]
 pub mod delay;
+#[cfg(feature = "device-selected")]
+pub mod dma;
+#[cfg(feature = "device-selected")]
+pub mod dma2d;
 #[cfg(all(feature = "device-selected", feature = "ethernet"))]
 pub mod ethernet;
 #[cfg(feature = "device-selected")]
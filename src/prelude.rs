@@ -4,6 +4,7 @@ pub use embedded_hal::prelude::*;
 pub use crate::adc::AdcExt as _stm32h7xx_hal_adc_AdcExt;
 pub use crate::dac::DacExt as _stm32h7xx_hal_dac_DacExt;
 pub use crate::delay::DelayExt as _stm32h7xx_hal_delay_DelayExt;
+pub use crate::dma2d::Dma2dExt as _stm32h7xx_hal_dma2d_Dma2dExt;
 pub use crate::exti::ExtiExt as _stm32h7xx_hal_delay_ExtiExt;
 pub use crate::flash::FlashExt as _stm32h7xx_hal_flash_FlashExt;
 pub use crate::gpio::GpioExt as _stm32h7xx_hal_gpio_GpioExt;
@@ -19,6 +20,8 @@ pub use crate::sai::SaiPdmExt as _stm32h7xx_hal_spi_SaiPdmExt;
 #[cfg(feature = "sdmmc")]
 pub use crate::sdmmc::SdmmcExt as _stm32h7xx_hal_sdmmc_SdmmcExt;
 pub use crate::serial::SerialExt as _stm32h7xx_hal_serial_SerialExt;
+pub use crate::serial::SerialSyncExt as _stm32h7xx_hal_serial_SerialSyncExt;
+pub use crate::serial::SmartcardExt as _stm32h7xx_hal_serial_SmartcardExt;
 pub use crate::spi::SpiExt as _stm32h7xx_hal_spi_SpiExt;
 pub use crate::time::U32Ext as _stm32h7xx_hal_time_U32Ext;
 pub use crate::timer::TimerExt as _stm32h7xx_hal_timer_TimerExt;
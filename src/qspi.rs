@@ -27,6 +27,9 @@
 //! qspi.write(0x00, &[0xAB, 0xCD]).unwrap();
 //! ```
 //!
+//! Reads and writes of any length are supported. Data is moved through the 32-byte FIFO as space
+//! or data becomes available, as indicated by the FIFO threshold flag.
+//!
 //! # Limitations
 //! This driver currently only supports indirect operation mode of the QSPI interface. It
 //! implements an 8-bit address followed by an arbitrary transaction length. It supports using
@@ -68,7 +71,8 @@ pub enum QspiMode {
 #[derive(Debug, Copy, Clone)]
 pub enum QspiError {
     Busy,
-    Underflow,
+    /// An invalid address was accessed in indirect mode
+    TransferError,
 }
 
 /// Indicates a specific QSPI bank to use.
@@ -341,9 +345,10 @@ impl Qspi {
             _ => panic!("Invalid QSPI frequency requested"),
         };
 
-        // Write the prescaler
-        regs.cr
-            .write(|w| unsafe { w.prescaler().bits(divisor as u8) });
+        // Write the prescaler, and a FIFO threshold of 1 byte
+        regs.cr.write(|w| unsafe {
+            w.prescaler().bits(divisor as u8).fthres().bits(0)
+        });
 
         match bank {
             Bank::One => regs.cr.modify(|_, w| w.fsel().clear_bit()),
@@ -373,6 +378,18 @@ impl Qspi {
         }
     }
 
+    /// Abort the current transfer after a transfer error, so that the
+    /// peripheral is ready for the next transfer.
+    fn abort(&mut self) {
+        self.rb.cr.modify(|_, w| w.abort().set_bit());
+
+        // Wait for the peripheral to indicate it is no longer busy.
+        while self.is_busy() {}
+
+        // Clear the transfer error and transfer complete flags.
+        self.rb.fcr.write(|w| w.ctef().set_bit().ctcf().set_bit());
+    }
+
     /// Configure the operational mode of the QSPI interface.
     ///
     /// # Args
@@ -428,15 +445,28 @@ impl Qspi {
             .ar
             .write(|w| unsafe { w.address().bits(addr as u32) });
 
-        // Write data to the FIFO in a byte-wise manner.
-        unsafe {
-            for byte in data {
+        // Write data to the FIFO in a byte-wise manner. With a FIFO
+        // threshold of 1, FTF is set whenever there is space for another
+        // byte, so that transfers are not limited by the FIFO size.
+        for byte in data {
+            while self.rb.sr.read().ftf().bit_is_clear() {
+                if self.rb.sr.read().tef().bit_is_set() {
+                    self.abort();
+                    return Err(QspiError::TransferError);
+                }
+            }
+            unsafe {
                 ptr::write_volatile(&self.rb.dr as *const _ as *mut u8, *byte);
             }
         }
 
         // Wait for the transaction to complete
-        while self.rb.sr.read().tcf().bit_is_clear() {}
+        while self.rb.sr.read().tcf().bit_is_clear() {
+            if self.rb.sr.read().tef().bit_is_set() {
+                self.abort();
+                return Err(QspiError::TransferError);
+            }
+        }
 
         // Wait for the peripheral to indicate it is no longer busy.
         while self.is_busy() {}
@@ -475,19 +505,26 @@ impl Qspi {
             .ar
             .write(|w| unsafe { w.address().bits(addr as u32) });
 
-        // Wait for the transaction to complete
-        while self.rb.sr.read().tcf().bit_is_clear() {}
-
-        // Check for underflow on the FIFO.
-        if (self.rb.sr.read().flevel().bits() as usize) < dest.len() {
-            return Err(QspiError::Underflow);
+        // Read data from the FIFO in a byte-wise manner. With a FIFO
+        // threshold of 1, FTF is set whenever a byte is available, so that
+        // transfers are not limited by the FIFO size.
+        for location in dest {
+            while self.rb.sr.read().ftf().bit_is_clear() {
+                if self.rb.sr.read().tef().bit_is_set() {
+                    self.abort();
+                    return Err(QspiError::TransferError);
+                }
+            }
+            *location = unsafe {
+                ptr::read_volatile(&self.rb.dr as *const _ as *const u8)
+            };
         }
 
-        // Read data from the FIFO in a byte-wise manner.
-        unsafe {
-            for location in dest {
-                *location =
-                    ptr::read_volatile(&self.rb.dr as *const _ as *const u8);
+        // Wait for the transaction to complete
+        while self.rb.sr.read().tcf().bit_is_clear() {
+            if self.rb.sr.read().tef().bit_is_set() {
+                self.abort();
+                return Err(QspiError::TransferError);
             }
         }
 
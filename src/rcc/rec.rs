@@ -395,6 +395,7 @@ peripheral_reset_and_enable_control! {
         Lptim4 [group clk: Lptim345],
         Lptim5 [group clk: Lptim345],
         I2c4 [kernel clk: I2c4 d3ccip "I2C4"],
+        Lpuart1 [kernel clk: Lpuart1(Variant) d3ccip "LPUART1"],
         Spi6 [kernel clk: Spi6(Variant) d3ccip "SPI6"],
         Sai4 [kernel clk_a: Sai4A(Variant) d3ccip
             "Sub-Block A of SAI4"]
@@ -55,6 +55,21 @@
 //!     info!("SD Card Connected: {:?}", card);
 //! }
 //! ```
+//!
+//! ## Internal DMA
+//!
+//! [`read_blocks`](crate::Sdmmc::read_blocks) and
+//! [`write_blocks`](crate::Sdmmc::write_blocks) move data with the SDMMC
+//! internal DMA (IDMA) when the IDMA can access the buffer, so that the FIFO
+//! cannot overflow or underrun at high bus clocks. Otherwise the data is
+//! moved through the FIFO by the CPU, as for single blocks. The double
+//! buffered variants alternate between two buffers, calling a closure for
+//! each buffer while the IDMA uses the other.
+//!
+//! Buffers used with the IDMA must be word aligned. Neither IDMA can access
+//! the ITCM or the DTCM, where the stack and statics are placed by
+//! default. The IDMA of `SDMMC1` also cannot access the SRAM in the D2 and
+//! D3 domains. The D-cache is not maintained by this driver.
 
 // Adapted from stm32f4xx-hal
 // https://github.com/stm32-rs/stm32f4xx-hal/blob/master/src/sdio.rs
@@ -77,6 +92,7 @@ use crate::gpio::{Alternate, AF10, AF11, AF12, AF9};
 //use crate::gpio:::{AF7, AF8};
 use crate::rcc::rec::{ResetEnable, SdmmcClkSelGetter};
 use crate::rcc::{rec, CoreClocks};
+use crate::stm32::sdmmc1::star;
 use crate::stm32::{SDMMC1, SDMMC2};
 
 pub trait PinClk<SDMMC> {}
@@ -236,6 +252,7 @@ pub enum Error {
     Crc,
     DataCrcFail,
     RxOverFlow,
+    DmaTransferError,
     NoCard,
     BadClock,
     SignalingSwitchFailed,
@@ -282,6 +299,8 @@ macro_rules! err_from_datapath_sm {
             return Err(Error::RxOverFlow);
         } else if $status.dtimeout().bit() {
             return Err(Error::Timeout);
+        } else if $status.idmate().bit() {
+            return Err(Error::DmaTransferError);
         }
     };
 }
@@ -292,6 +311,30 @@ enum Dir {
     HostToCard,
 }
 
+/// Returns true if an internal DMA can access `length` bytes at
+/// `address`. Neither IDMA can access the ITCM or the DTCM, and if
+/// `d2_d3_sram` is false the SRAM in the D2 and D3 domains is also not
+/// accessible
+fn idma_accessible(address: usize, length: usize, d2_d3_sram: bool) -> bool {
+    let end = address + length;
+    let outside = |start: usize, stop: usize| end <= start || address >= stop;
+
+    outside(0x0000_0000, 0x0001_0000) // ITCM
+        && outside(0x2000_0000, 0x2002_0000) // DTCM
+        && (d2_d3_sram || outside(0x3000_0000, 0x4000_0000))
+}
+
+/// Internal DMA configuration for a data transfer
+enum Idma {
+    /// Data is moved through the FIFO by the CPU
+    Disabled,
+    /// Single buffer at the given address
+    Single(u32),
+    /// Two buffers at the given addresses, each of the given length in
+    /// bytes
+    Double(u32, u32, u32),
+}
+
 enum PowerCtrl {
     Off = 0b00,
     On = 0b11,
@@ -390,7 +433,7 @@ impl<S> Sdmmc<S> {
 }
 
 macro_rules! sdmmc {
-    ($($SDMMCX:ident: ($sdmmcX:ident, $Rec:ident),)+) => {
+    ($($SDMMCX:ident: ($sdmmcX:ident, $Rec:ident, $d2_d3_sram:expr),)+) => {
         $(
             impl SdmmcExt<$SDMMCX> for $SDMMCX {
                 type Rec = rec::$Rec;
@@ -652,6 +695,7 @@ macro_rules! sdmmc {
                     length_bytes: u32,
                     block_size: u8,
                     direction: Dir,
+                    idma: Idma,
                 ) {
                     assert!(block_size <= 14, "Block size up to 2^14 bytes");
 
@@ -672,6 +716,25 @@ macro_rules! sdmmc {
                         || self.sdmmc.star.read().cpsmact().bit_is_set()
                     {}
 
+                    // Internal DMA. Must be configured before the data
+                    // transfer is enabled
+                    match idma {
+                        Idma::Disabled => self.sdmmc.idmactrlr.reset(),
+                        Idma::Single(address) => {
+                            self.sdmmc.idmabase0r.write(|w| unsafe { w.idmabase0().bits(address) });
+                            self.sdmmc.idmactrlr.write(|w| w.idmaen().set_bit());
+                        }
+                        Idma::Double(address0, address1, length) => {
+                            self.sdmmc.idmabase0r.write(|w| unsafe { w.idmabase0().bits(address0) });
+                            self.sdmmc.idmabase1r.write(|w| unsafe { w.idmabase1().bits(address1) });
+                            // Buffer size in units of 32 bytes
+                            self.sdmmc.idmabsizer.write(|w| unsafe { w.idmabndt().bits((length / 32) as u8) });
+                            self.sdmmc.idmactrlr.write(|w| {
+                                w.idmaen().set_bit().idmabmode().set_bit().idmabact().clear_bit()
+                            });
+                        }
+                    }
+
                     // Data timeout, in bus cycles
                     self.sdmmc
                         .dtimer
@@ -704,30 +767,10 @@ macro_rules! sdmmc {
                     self.cmd(Cmd::set_block_length(512))?; // CMD16
 
                     // Setup read command
-                    self.start_datapath_transfer(512, 9, Dir::CardToHost);
+                    self.start_datapath_transfer(512, 9, Dir::CardToHost, Idma::Disabled);
                     self.cmd(Cmd::read_single_block(address))?;
 
-                    let mut i = 0;
-                    let mut status;
-                    while {
-                        status = self.sdmmc.star.read();
-                        !(status.rxoverr().bit()
-                          || status.dcrcfail().bit()
-                          || status.dtimeout().bit()
-                          || status.dataend().bit())
-                    } {
-                        if status.rxfifohf().bit() {
-                            for _ in 0..8 {
-                                let bytes = self.sdmmc.fifor.read().bits().to_le_bytes();
-                                buffer[i..i + 4].copy_from_slice(&bytes);
-                                i += 4;
-                            }
-                        }
-
-                        if i >= buffer.len() {
-                            break;
-                        }
-                    }
+                    let status = self.read_fifo(buffer);
 
                     err_from_datapath_sm!(status);
 
@@ -737,6 +780,10 @@ macro_rules! sdmmc {
                 /// Read mutliple blocks from card. The length of the buffer
                 /// must be multiple of 512.
                 ///
+                /// The internal DMA is used if the buffer is word aligned
+                /// and the internal DMA can access it. Otherwise the data
+                /// is read from the FIFO by the CPU.
+                ///
                 /// `address` is the block address.
                 pub fn read_blocks(
                     &mut self,
@@ -751,32 +798,85 @@ macro_rules! sdmmc {
                     self.cmd(Cmd::set_block_length(512))?; // CMD16
 
                     // Setup read command
-                    self.start_datapath_transfer(512 * n_blocks as u32, 9, Dir::CardToHost);
+                    let status = if Self::idma_accessible(buffer) {
+                        let idma = Idma::Single(buffer.as_mut_ptr() as u32);
+                        self.start_datapath_transfer(512 * n_blocks as u32, 9, Dir::CardToHost, idma);
+                        self.cmd(Cmd::read_multiple_blocks(address))?;
+
+                        self.wait_datapath_end()
+                    } else {
+                        self.start_datapath_transfer(512 * n_blocks as u32, 9, Dir::CardToHost, Idma::Disabled);
+                        self.cmd(Cmd::read_multiple_blocks(address))?;
+
+                        self.read_fifo(buffer)
+                    };
+
+                    self.cmd(Cmd::stop_transmission())?; // CMD12
+                    self.sdmmc.idmactrlr.reset();
+
+                    err_from_datapath_sm!(status);
+
+                    Ok(())
+                }
+
+                /// Read `n_blocks` blocks from card using the internal DMA
+                /// in double buffered mode.
+                ///
+                /// The IDMA alternates between the two buffers, which must
+                /// have the same length. This must be a multiple of 512 and
+                /// at most 7680 bytes, the largest multiple of 512 that the
+                /// IDMA buffer size register can hold. `n_blocks` must be a
+                /// multiple of the number of blocks per buffer. The buffers
+                /// must be word aligned and accessible by the internal DMA. Each time a buffer is
+                /// filled, `f` is called with its contents while the IDMA
+                /// fills the other buffer. `f` must return before the
+                /// other buffer is filled.
+                ///
+                /// `address` is the block address.
+                pub fn read_blocks_double_buffered<F>(
+                    &mut self,
+                    address: u32,
+                    n_blocks: u32,
+                    buffers: (&mut [u8], &mut [u8]),
+                    mut f: F,
+                ) -> Result<(), Error>
+                where
+                    F: FnMut(&[u8]),
+                {
+                    let _card = self.card()?;
+
+                    let length = Self::check_double_buffers(n_blocks, &buffers);
+                    self.cmd(Cmd::set_block_length(512))?; // CMD16
+
+                    // Setup read command
+                    let idma = Idma::Double(
+                        buffers.0.as_mut_ptr() as u32,
+                        buffers.1.as_mut_ptr() as u32,
+                        length,
+                    );
+                    self.start_datapath_transfer(512 * n_blocks, 9, Dir::CardToHost, idma);
                     self.cmd(Cmd::read_multiple_blocks(address))?;
 
-                    let mut i = 0;
-                    let mut status;
-                    while {
-                        status = self.sdmmc.star.read();
-                        !(status.rxoverr().bit()
-                          || status.dcrcfail().bit()
-                          || status.dtimeout().bit()
-                          || status.dataend().bit())
-                    } {
-                        if status.rxfifohf().bit() {
-                            for _ in 0..8 {
-                                let bytes = self.sdmmc.fifor.read().bits().to_le_bytes();
-                                buffer[i..i + 4].copy_from_slice(&bytes);
-                                i += 4;
-                            }
+                    let mut status = self.sdmmc.star.read();
+                    for n in 0..(512 * n_blocks / length) {
+                        status = self.wait_idma_buffer();
+                        if !status.idmabtc().bit() && !status.dataend().bit() {
+                            break;
                         }
 
-                        if i >= buffer.len() {
-                            break;
+                        // The IDMA has switched to the other buffer
+                        if n % 2 == 0 {
+                            f(buffers.0);
+                        } else {
+                            f(buffers.1);
                         }
                     }
+                    if !status.dataend().bit() {
+                        status = self.wait_datapath_end();
+                    }
 
                     self.cmd(Cmd::stop_transmission())?; // CMD12
+                    self.sdmmc.idmactrlr.reset();
 
                     err_from_datapath_sm!(status);
 
@@ -794,9 +894,193 @@ macro_rules! sdmmc {
                     self.cmd(Cmd::set_block_length(512))?; // CMD16
 
                     // Setup write command
-                    self.start_datapath_transfer(512, 9, Dir::HostToCard);
+                    self.start_datapath_transfer(512, 9, Dir::HostToCard, Idma::Disabled);
                     self.cmd(Cmd::write_single_block(address))?; // CMD24
 
+                    let status = self.write_fifo(buffer);
+
+                    err_from_datapath_sm!(status);
+                    self.clear_static_interrupt_flags();
+
+                    self.wait_write_complete()
+                }
+
+                /// Write multiple blocks to card. The length of the buffer
+                /// must be multiple of 512.
+                ///
+                /// The internal DMA is used if the buffer is word aligned
+                /// and the internal DMA can access it. Otherwise the data
+                /// is written to the FIFO by the CPU.
+                ///
+                /// `address` is the block address.
+                pub fn write_blocks(
+                    &mut self,
+                    address: u32,
+                    buffer: &[u8],
+                ) -> Result<(), Error> {
+                    let _card = self.card()?;
+
+                    assert!(buffer.len() % 512 == 0,
+                            "Buffer length must be a multiple of 512");
+                    let n_blocks = buffer.len() / 512;
+                    self.cmd(Cmd::set_block_length(512))?; // CMD16
+
+                    // Setup write command
+                    let status = if Self::idma_accessible(buffer) {
+                        let idma = Idma::Single(buffer.as_ptr() as u32);
+                        self.start_datapath_transfer(512 * n_blocks as u32, 9, Dir::HostToCard, idma);
+                        self.cmd(Cmd::write_multiple_blocks(address))?; // CMD25
+
+                        self.wait_datapath_end()
+                    } else {
+                        self.start_datapath_transfer(512 * n_blocks as u32, 9, Dir::HostToCard, Idma::Disabled);
+                        self.cmd(Cmd::write_multiple_blocks(address))?; // CMD25
+
+                        self.write_fifo(buffer)
+                    };
+
+                    self.cmd(Cmd::stop_transmission())?; // CMD12
+                    self.sdmmc.idmactrlr.reset();
+
+                    err_from_datapath_sm!(status);
+                    self.clear_static_interrupt_flags();
+
+                    self.wait_write_complete()
+                }
+
+                /// Write `n_blocks` blocks to card using the internal DMA
+                /// in double buffered mode.
+                ///
+                /// The IDMA alternates between the two buffers, which must
+                /// have the same length. This must be a multiple of 512 and
+                /// at most 7680 bytes, the largest multiple of 512 that the
+                /// IDMA buffer size register can hold. `n_blocks` must be a
+                /// multiple of the number of blocks per buffer. The buffers
+                /// must be word aligned and accessible by the internal DMA. `f` is called to fill
+                /// each buffer before it is written, for the first two
+                /// buffers before the transfer starts and for the remaining
+                /// buffers while the IDMA writes the other buffer. `f` must
+                /// return before the other buffer is written.
+                ///
+                /// `address` is the block address.
+                pub fn write_blocks_double_buffered<F>(
+                    &mut self,
+                    address: u32,
+                    n_blocks: u32,
+                    buffers: (&mut [u8], &mut [u8]),
+                    mut f: F,
+                ) -> Result<(), Error>
+                where
+                    F: FnMut(&mut [u8]),
+                {
+                    let _card = self.card()?;
+
+                    let length = Self::check_double_buffers(n_blocks, &buffers);
+                    let n_buffers = 512 * n_blocks / length;
+                    self.cmd(Cmd::set_block_length(512))?; // CMD16
+
+                    // Fill the first buffers
+                    f(buffers.0);
+                    if n_buffers > 1 {
+                        f(buffers.1);
+                    }
+
+                    // Setup write command
+                    let idma = Idma::Double(
+                        buffers.0.as_mut_ptr() as u32,
+                        buffers.1.as_mut_ptr() as u32,
+                        length,
+                    );
+                    self.start_datapath_transfer(512 * n_blocks, 9, Dir::HostToCard, idma);
+                    self.cmd(Cmd::write_multiple_blocks(address))?; // CMD25
+
+                    let mut status = self.sdmmc.star.read();
+                    for n in 0..n_buffers {
+                        status = self.wait_idma_buffer();
+                        if !status.idmabtc().bit() && !status.dataend().bit() {
+                            break;
+                        }
+
+                        // The IDMA has switched to the other buffer. Refill
+                        // the completed buffer if it will be used again
+                        if n + 2 < n_buffers {
+                            if n % 2 == 0 {
+                                f(buffers.0);
+                            } else {
+                                f(buffers.1);
+                            }
+                        }
+                    }
+                    if !status.dataend().bit() {
+                        status = self.wait_datapath_end();
+                    }
+
+                    self.cmd(Cmd::stop_transmission())?; // CMD12
+                    self.sdmmc.idmactrlr.reset();
+
+                    err_from_datapath_sm!(status);
+                    self.clear_static_interrupt_flags();
+
+                    self.wait_write_complete()
+                }
+
+                /// Check the buffers for a double buffered transfer, and
+                /// return the length of each buffer in bytes
+                fn check_double_buffers(n_blocks: u32, buffers: &(&mut [u8], &mut [u8])) -> u32 {
+                    let length = buffers.0.len();
+                    assert_eq!(length, buffers.1.len(), "Buffer lengths must be equal");
+                    // IDMABNDT is 8 bits wide, in units of 32 bytes, so a
+                    // buffer holds at most 8160 bytes. The largest whole
+                    // number of blocks that fits is 15
+                    assert!(length % 512 == 0 && length > 0 && length <= 7680,
+                            "Buffer length must be a multiple of 512, up to 7680");
+                    assert!(Self::idma_accessible(buffers.0)
+                            && Self::idma_accessible(buffers.1),
+                            "Buffers must be word aligned and accessible by the internal DMA");
+                    let length = length as u32;
+                    assert!((512 * n_blocks) % length == 0,
+                            "Data length must be a multiple of the buffer length");
+                    length
+                }
+
+                /// Returns true if `buffer` is word aligned and the internal
+                /// DMA of this peripheral can access it
+                fn idma_accessible(buffer: &[u8]) -> bool {
+                    let address = buffer.as_ptr() as usize;
+
+                    address & 3 == 0 && idma_accessible(address, buffer.len(), $d2_d3_sram)
+                }
+
+                /// Read `buffer` from the FIFO, until it is full or the
+                /// data transfer ends with an error
+                fn read_fifo(&self, buffer: &mut [u8]) -> star::R {
+                    let mut i = 0;
+                    let mut status;
+                    while {
+                        status = self.sdmmc.star.read();
+                        !(status.rxoverr().bit()
+                          || status.dcrcfail().bit()
+                          || status.dtimeout().bit()
+                          || status.dataend().bit())
+                    } {
+                        if status.rxfifohf().bit() {
+                            for _ in 0..8 {
+                                let bytes = self.sdmmc.fifor.read().bits().to_le_bytes();
+                                buffer[i..i + 4].copy_from_slice(&bytes);
+                                i += 4;
+                            }
+                        }
+
+                        if i >= buffer.len() {
+                            break;
+                        }
+                    }
+                    status
+                }
+
+                /// Write `buffer` to the FIFO, and wait for the end of the
+                /// data transfer or an error
+                fn write_fifo(&self, buffer: &[u8]) -> star::R {
                     let mut i = 0;
                     let mut status;
                     while {
@@ -806,7 +1090,7 @@ macro_rules! sdmmc {
                           || status.dtimeout().bit()
                           || status.dataend().bit())
                     } {
-                        if status.txfifohe().bit() {
+                        if i < buffer.len() && status.txfifohe().bit() {
                             for _ in 0..8 {
                                 let mut wb = [0u8; 4];
                                 wb.copy_from_slice(&buffer[i..i + 4]);
@@ -815,15 +1099,48 @@ macro_rules! sdmmc {
                                 i += 4;
                             }
                         }
-
-                        if i >= buffer.len() {
-                            break;
-                        }
                     }
+                    status
+                }
 
-                    err_from_datapath_sm!(status);
-                    self.clear_static_interrupt_flags();
+                /// Wait for the end of a data transfer, or an error
+                fn wait_datapath_end(&self) -> star::R {
+                    let mut status;
+                    while {
+                        status = self.sdmmc.star.read();
+                        !(status.rxoverr().bit()
+                          || status.txunderr().bit()
+                          || status.dcrcfail().bit()
+                          || status.dtimeout().bit()
+                          || status.idmate().bit()
+                          || status.dataend().bit())
+                    } {}
+                    status
+                }
 
+                /// Wait for the IDMA to complete a buffer in double
+                /// buffered mode, the end of the data transfer, or an
+                /// error. A buffer transfer complete flag is cleared.
+                fn wait_idma_buffer(&self) -> star::R {
+                    let mut status;
+                    while {
+                        status = self.sdmmc.star.read();
+                        !(status.rxoverr().bit()
+                          || status.txunderr().bit()
+                          || status.dcrcfail().bit()
+                          || status.dtimeout().bit()
+                          || status.idmate().bit()
+                          || status.idmabtc().bit()
+                          || status.dataend().bit())
+                    } {}
+                    if status.idmabtc().bit() {
+                        self.sdmmc.icr.write(|w| w.idmabtcc().set_bit());
+                    }
+                    status
+                }
+
+                /// Wait for the card to finish programming after a write
+                fn wait_write_complete(&mut self) -> Result<(), Error> {
                     let mut timeout: u32 = 0xFFFF_FFFF;
 
                     // Try to read card status (ACMD13)
@@ -859,7 +1176,7 @@ macro_rules! sdmmc {
                     self.cmd(Cmd::app_cmd(card.rca << 16))?; // APP
 
                     // Prepare the transfer
-                    self.start_datapath_transfer(64, 6, Dir::CardToHost);
+                    self.start_datapath_transfer(64, 6, Dir::CardToHost, Idma::Disabled);
                     self.cmd(Cmd::card_status(0))?; // ACMD13
 
                     let mut status = [0u32; 16];
@@ -900,7 +1217,7 @@ macro_rules! sdmmc {
                     self.cmd(Cmd::set_block_length(8))?; // CMD16
                     self.cmd(Cmd::app_cmd(card.rca << 16))?;
 
-                    self.start_datapath_transfer(8, 3, Dir::CardToHost);
+                    self.start_datapath_transfer(8, 3, Dir::CardToHost, Idma::Disabled);
                     self.cmd(Cmd::cmd51())?;
 
                     let mut scr = [0; 2];
@@ -957,7 +1274,7 @@ macro_rules! sdmmc {
                         };
 
                     // Prepare the transfer
-                    self.start_datapath_transfer(64, 6, Dir::CardToHost);
+                    self.start_datapath_transfer(64, 6, Dir::CardToHost, Idma::Disabled);
                     self.cmd(Cmd::cmd6(set_function))?; // CMD6
 
                     let mut status = [0u32; 16];
@@ -1144,8 +1461,8 @@ macro_rules! sdmmc {
 }
 
 sdmmc! {
-    SDMMC1: (sdmmc1, Sdmmc1),
-    SDMMC2: (sdmmc2, Sdmmc2),
+    SDMMC1: (sdmmc1, Sdmmc1, false),
+    SDMMC2: (sdmmc2, Sdmmc2, true),
 }
 
 /// SD card Commands
@@ -1221,6 +1538,11 @@ impl Cmd {
         Cmd::new(24, addr, Response::Short)
     }
 
+    /// CMD25: Multiple Block Write
+    const fn write_multiple_blocks(addr: u32) -> Cmd {
+        Cmd::new(25, addr, Response::Short)
+    }
+
     const fn app_op_cmd(arg: u32) -> Cmd {
         Cmd::new(41, arg, Response::Short)
     }
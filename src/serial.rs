@@ -1,19 +1,29 @@
 //! Serial
 
+use core::cmp;
 use core::fmt;
 use core::marker::PhantomData;
-use core::ptr;
 
 use embedded_hal::blocking::serial as serial_block;
 use embedded_hal::prelude::*;
 use embedded_hal::serial;
+use embedded_hal::spi;
 use nb::block;
 
+use crate::dma::{
+    dma::DMAReq,
+    traits::{MemoryAccess, Stream, TargetAddress},
+    MemoryToPeripheral, PeripheralToMemory, StaticReadBuffer,
+    StaticWriteBuffer, Transfer,
+};
 use crate::stm32;
-use crate::stm32::rcc::d2ccip2r;
-use crate::stm32::usart1::cr1::{M0_A as M0, PCE_A as PCE, PS_A as PS};
+use crate::stm32::rcc::{d2ccip2r, d3ccipr};
+use crate::stm32::usart1::cr1::{
+    M0_A as M0, M1_A as M1, PCE_A as PCE, PS_A as PS,
+};
 use stm32h7::Variant::Val;
 
+use crate::stm32::{DMAMUX1, LPUART1};
 use crate::stm32::{UART4, UART5, UART7, UART8};
 use crate::stm32::{USART1, USART2, USART3, USART6};
 
@@ -21,18 +31,21 @@ use crate::gpio::gpioa::{
     PA0, PA1, PA10, PA11, PA12, PA15, PA2, PA3, PA4, PA8, PA9,
 };
 use crate::gpio::gpiob::{
-    PB10, PB11, PB12, PB13, PB14, PB15, PB3, PB4, PB5, PB6, PB7, PB8, PB9,
+    PB0, PB10, PB11, PB12, PB13, PB14, PB15, PB3, PB4, PB5, PB6, PB7, PB8, PB9,
 };
-use crate::gpio::gpioc::{PC10, PC11, PC12, PC6, PC7, PC8};
-use crate::gpio::gpiod::{PD0, PD1, PD10, PD2, PD5, PD6, PD7, PD8, PD9};
-use crate::gpio::gpioe::{PE0, PE1, PE7, PE8};
-use crate::gpio::gpiof::{PF6, PF7};
-use crate::gpio::gpiog::{PG14, PG7, PG9};
+use crate::gpio::gpioc::{PC10, PC11, PC12, PC6, PC7, PC8, PC9};
+use crate::gpio::gpiod::{
+    PD0, PD1, PD10, PD11, PD12, PD14, PD15, PD2, PD3, PD4, PD5, PD6, PD7, PD8,
+    PD9,
+};
+use crate::gpio::gpioe::{PE0, PE1, PE10, PE7, PE8, PE9};
+use crate::gpio::gpiof::{PF6, PF7, PF8, PF9};
+use crate::gpio::gpiog::{PG12, PG13, PG14, PG15, PG7, PG8, PG9};
 use crate::gpio::gpioh::{PH13, PH14};
 use crate::gpio::gpioi::PI9;
 use crate::gpio::gpioj::{PJ8, PJ9};
 
-use crate::gpio::{Alternate, AF11, AF14, AF4, AF6, AF7, AF8};
+use crate::gpio::{Alternate, AF11, AF14, AF3, AF4, AF6, AF7, AF8};
 use crate::rcc::{rec, CoreClocks, ResetEnable};
 use crate::time::Hertz;
 
@@ -49,6 +62,13 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Transmitted data was NACKed by the smartcard, including all
+    /// automatic retries
+    Nack,
+    /// LIN checksum mismatch
+    Checksum,
+    /// Automatic baudrate detection failed
+    AutoBaud,
     #[doc(hidden)]
     _Extensible,
 }
@@ -61,12 +81,44 @@ pub enum Event {
     Txe,
     /// Idle line state detected
     Idle,
+    /// Match character received
+    CharacterMatch,
+    /// LIN break detected. LPUART1 has no LIN mode, and listening for this
+    /// event has no effect
+    LinBreak,
+    /// Receiver timeout expired. LPUART1 has no receiver timeout, and
+    /// listening for this event has no effect
+    ReceiverTimeout,
+}
+
+/// Trigger for waking the system from Stop mode
+pub enum Wakeup {
+    /// Start bit detected
+    StartBit,
+    /// New data has been received
+    Rxne,
+}
+
+/// Trigger for waking the receiver from mute mode
+pub enum MuteWakeup {
+    /// Idle line detected
+    IdleLine,
+    /// Address mark character, with its most significant bit set,
+    /// received with the 4 least significant bits matching the given
+    /// address
+    AddressMark4Bit(u8),
+    /// Address mark character, with its most significant bit set,
+    /// received with the 7 least significant bits matching the given
+    /// address
+    AddressMark7Bit(u8),
 }
 
 pub mod config {
     use crate::time::Hertz;
 
+    /// Word length, including the parity bit if parity is enabled
     pub enum WordLength {
+        DataBits7,
         DataBits8,
         DataBits9,
     }
@@ -88,11 +140,91 @@ pub mod config {
         STOP1P5,
     }
 
+    pub enum AutoBaud {
+        AutoBaudDisabled,
+        #[doc = "Measure the start bit. The first character must start with a 1 bit"]
+        AutoBaudStartBit,
+        #[doc = "Measure from falling edge to falling edge. The first character must start with 10"]
+        AutoBaudFallingEdge,
+        #[doc = "Detect a 0x7F character"]
+        AutoBaud0x7F,
+        #[doc = "Detect a 0x55 character"]
+        AutoBaud0x55,
+    }
+
+    pub enum BreakLength {
+        #[doc = "10-bit break detection"]
+        Break10,
+        #[doc = "11-bit break detection"]
+        Break11,
+    }
+
+    pub enum FlowControl {
+        FlowControlNone,
+        #[doc = "Hardware flow control using the RTS and CTS pins"]
+        FlowControlRtsCts,
+        #[doc = "RS-485 driver enable using the DE pin"]
+        FlowControlRs485,
+    }
+
+    pub enum DePolarity {
+        #[doc = "DE is active high"]
+        DePolarityHigh,
+        #[doc = "DE is active low"]
+        DePolarityLow,
+    }
+
+    /// A duration measured in bit times, with a resolution of 1/16 bit
+    /// time
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct BitTime {
+        sixteenths: u16,
+    }
+
+    impl BitTime {
+        /// A duration of `bits` whole bit times
+        pub const fn bits(bits: u8) -> Self {
+            BitTime {
+                sixteenths: bits as u16 * 16,
+            }
+        }
+
+        /// A duration of `sixteenths` sixteenths of a bit time
+        pub const fn sixteenths(sixteenths: u16) -> Self {
+            BitTime { sixteenths }
+        }
+
+        /// Returns the duration in sixteenths of a bit time
+        pub fn as_sixteenths(self) -> u16 {
+            self.sixteenths
+        }
+    }
+
     pub struct Config {
         pub baudrate: Hertz,
         pub wordlength: WordLength,
         pub parity: Parity,
         pub stopbits: StopBits,
+        pub flowcontrol: FlowControl,
+        pub depolarity: DePolarity,
+        /// Time from asserting DE to the start bit
+        pub de_assertion_time: BitTime,
+        /// Time from the end of the last stop bit to deasserting DE
+        pub de_deassertion_time: BitTime,
+        /// Swap the functions of the Tx and Rx pins
+        pub swap_tx_rx: bool,
+        /// Invert the level of the Rx pin
+        pub invert_rx: bool,
+        /// Invert the level of the Tx pin
+        pub invert_tx: bool,
+        /// Invert the logical value of data bits, including parity
+        pub invert_data: bool,
+        /// Transmit and receive the most significant bit first
+        pub msb_first: bool,
+        /// LIN mode
+        pub lin: bool,
+        pub lin_break_length: BreakLength,
+        pub autobaud: AutoBaud,
     }
 
     impl Config {
@@ -116,6 +248,11 @@ pub mod config {
             self
         }
 
+        pub fn wordlength_7(mut self) -> Self {
+            self.wordlength = WordLength::DataBits7;
+            self
+        }
+
         pub fn wordlength_8(mut self) -> Self {
             self.wordlength = WordLength::DataBits8;
             self
@@ -130,10 +267,109 @@ pub mod config {
             self.stopbits = stopbits;
             self
         }
+
+        /// Enables hardware flow control. Transmission is paused whilst
+        /// CTS is high, and RTS is driven high whilst the receiver
+        /// cannot accept more data
+        pub fn rts_cts(mut self) -> Self {
+            self.flowcontrol = FlowControl::FlowControlRtsCts;
+            self
+        }
+
+        /// Enables RS-485 driver enable mode. The DE pin is asserted
+        /// whilst the transmitter is active
+        pub fn rs485(mut self) -> Self {
+            self.flowcontrol = FlowControl::FlowControlRs485;
+            self
+        }
+
+        pub fn de_polarity(mut self, depolarity: DePolarity) -> Self {
+            self.depolarity = depolarity;
+            self
+        }
+
+        /// Sets the time from asserting DE to the start bit.
+        ///
+        /// USART1-8 count this time in sample times, which are 1/16 bit
+        /// time with 16x oversampling and 1/8 bit time with 8x
+        /// oversampling. LPUART1 counts it in kernel clock cycles. The
+        /// time is rounded up to a whole count, and the constructor
+        /// returns `InvalidConfig::Unsupported` if that exceeds 31.
+        pub fn de_assertion_time(mut self, time: BitTime) -> Self {
+            self.de_assertion_time = time;
+            self
+        }
+
+        /// Sets the time from the end of the last stop bit to deasserting
+        /// DE. It is counted and limited as described for
+        /// `de_assertion_time`.
+        pub fn de_deassertion_time(mut self, time: BitTime) -> Self {
+            self.de_deassertion_time = time;
+            self
+        }
+
+        /// Swaps the functions of the Tx and Rx pins, for boards with
+        /// crossed wiring. The pins are still passed to the constructor in
+        /// (Tx, Rx) order
+        pub fn swap_tx_rx(mut self) -> Self {
+            self.swap_tx_rx = true;
+            self
+        }
+
+        /// Inverts the level of the Rx pin, so that idle is low
+        pub fn invert_rx(mut self) -> Self {
+            self.invert_rx = true;
+            self
+        }
+
+        /// Inverts the level of the Tx pin, so that idle is low
+        pub fn invert_tx(mut self) -> Self {
+            self.invert_tx = true;
+            self
+        }
+
+        /// Inverts the logical value of the transmitted and received data
+        /// bits, including the parity bit
+        pub fn invert_data(mut self) -> Self {
+            self.invert_data = true;
+            self
+        }
+
+        /// Transmits and receives the most significant data bit first,
+        /// following the start bit
+        pub fn msb_first(mut self) -> Self {
+            self.msb_first = true;
+            self
+        }
+
+        /// Enables LIN mode, detecting breaks of `break_length` or
+        /// longer. LIN mode requires 1 stop bit, and is not supported by
+        /// LPUART1
+        pub fn lin(mut self, break_length: BreakLength) -> Self {
+            self.lin = true;
+            self.lin_break_length = break_length;
+            self
+        }
+
+        /// Enables automatic baudrate detection on the first received
+        /// character. The baudrate is used until detection
+        /// completes. Not supported by LPUART1
+        pub fn autobaud(mut self, autobaud: AutoBaud) -> Self {
+            self.autobaud = autobaud;
+            self
+        }
     }
 
-    #[derive(Debug)]
-    pub struct InvalidConfig;
+    /// Reason a serial configuration cannot be applied
+    #[derive(Debug, Clone, Copy)]
+    pub enum InvalidConfig {
+        /// The baudrate cannot be generated from the kernel clock within
+        /// 2% tolerance. Contains the closest achievable baudrate, and its
+        /// error relative to the requested baudrate in parts per million
+        Baudrate { achieved: Hertz, error_ppm: u32 },
+        /// The combination of settings is not supported by the peripheral
+        Unsupported,
+    }
 
     impl Default for Config {
         fn default() -> Config {
@@ -142,6 +378,18 @@ pub mod config {
                 wordlength: WordLength::DataBits8,
                 parity: Parity::ParityNone,
                 stopbits: StopBits::STOP1,
+                flowcontrol: FlowControl::FlowControlNone,
+                depolarity: DePolarity::DePolarityHigh,
+                de_assertion_time: BitTime::bits(0),
+                de_deassertion_time: BitTime::bits(0),
+                swap_tx_rx: false,
+                invert_rx: false,
+                invert_tx: false,
+                invert_data: false,
+                msb_first: false,
+                lin: false,
+                lin_break_length: BreakLength::Break10,
+                autobaud: AutoBaud::AutoBaudDisabled,
             }
         }
     }
@@ -154,12 +402,147 @@ pub mod config {
             }
         }
     }
+
+    /// Smartcard (ISO 7816-3) configuration
+    pub struct SmartcardConfig {
+        pub baudrate: Hertz,
+        /// Frequency of the clock supplied to the card on the Ck pin
+        pub clock: Hertz,
+        /// Guard time of each transmitted character, in bit times
+        pub guard_time: u8,
+        /// Send a NACK when a received character has a parity error
+        pub nack: bool,
+        /// Number of automatic retries after a NACK, up to 7
+        pub retries: u8,
+    }
+
+    impl SmartcardConfig {
+        pub fn baudrate(mut self, baudrate: impl Into<Hertz>) -> Self {
+            self.baudrate = baudrate.into();
+            self
+        }
+
+        pub fn clock(mut self, clock: impl Into<Hertz>) -> Self {
+            self.clock = clock.into();
+            self
+        }
+
+        pub fn guard_time(mut self, guard_time: u8) -> Self {
+            self.guard_time = guard_time;
+            self
+        }
+
+        pub fn nack(mut self, nack: bool) -> Self {
+            self.nack = nack;
+            self
+        }
+
+        pub fn retries(mut self, retries: u8) -> Self {
+            self.retries = retries;
+            self
+        }
+    }
+
+    impl Default for SmartcardConfig {
+        fn default() -> SmartcardConfig {
+            SmartcardConfig {
+                baudrate: Hertz(9_600),  // 372 clocks per bit
+                clock: Hertz(3_571_200), // 3.5712 MHz
+                guard_time: 12,
+                nack: true,
+                retries: 3,
+            }
+        }
+    }
+}
+
+pub mod lin {
+    //! LIN frame helpers
+
+    /// LIN checksum model
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Checksum {
+        /// Classic checksum, over the data only. Used by LIN 1.x and by
+        /// diagnostic frames
+        Classic,
+        /// Enhanced checksum, over the protected identifier and the data.
+        /// Used by LIN 2.x
+        Enhanced,
+    }
+
+    /// Returns the protected identifier for frame `id`, with the parity
+    /// bits P0 and P1 in bits 6 and 7
+    pub fn pid(id: u8) -> u8 {
+        let id = id & 0x3F;
+        let bit = |n: u8| (id >> n) & 1;
+
+        let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+        let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+        id | (p0 << 6) | (p1 << 7)
+    }
+
+    /// Returns the checksum of a frame with protected identifier `pid`
+    /// and `data`
+    pub fn checksum(model: Checksum, pid: u8, data: &[u8]) -> u8 {
+        let initial = match model {
+            Checksum::Classic => 0,
+            Checksum::Enhanced => u16::from(pid),
+        };
+
+        // Sum with carry
+        let sum = data.iter().fold(initial, |sum, &byte| {
+            let sum = sum + u16::from(byte);
+            if sum > 0xFF {
+                sum - 0xFF
+            } else {
+                sum
+            }
+        });
+
+        !(sum as u8)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn protected_identifier() {
+            assert_eq!(pid(0x00), 0x80);
+            assert_eq!(pid(0x3C), 0x3C);
+            assert_eq!(pid(0x3D), 0x7D);
+            assert_eq!(pid(0x10), 0x50);
+            // Parity bits of the argument are ignored
+            assert_eq!(pid(0xFC), 0x3C);
+        }
+
+        #[test]
+        fn classic_checksum() {
+            assert_eq!(checksum(Checksum::Classic, 0x80, &[1, 2, 3]), 0xF9);
+            assert_eq!(checksum(Checksum::Classic, 0x80, &[0xFF, 0xFF]), 0x00);
+            assert_eq!(checksum(Checksum::Classic, 0x80, &[]), 0xFF);
+        }
+
+        #[test]
+        fn enhanced_checksum() {
+            assert_eq!(checksum(Checksum::Enhanced, 0x80, &[0x01]), 0x7E);
+            assert_eq!(checksum(Checksum::Enhanced, 0x80, &[0x80]), 0xFE);
+        }
+    }
 }
 
 pub trait Pins<USART> {}
 pub trait PinTx<USART> {}
+/// A Tx pin that can be used for single-wire half-duplex communication
+pub trait PinTxHalfDuplex<USART>: PinTx<USART> {
+    fn set_open_drain(self) -> Self;
+}
 pub trait PinRx<USART> {}
 pub trait PinCk<USART> {}
+pub trait PinRts<USART> {}
+pub trait PinCts<USART> {}
+pub trait PinDe<USART> {}
 
 impl<USART, TX, RX> Pins<USART> for (TX, RX)
 where
@@ -168,18 +551,48 @@ where
 {
 }
 
+impl<USART, TX, RX, RTS, CTS> Pins<USART> for (TX, RX, RTS, CTS)
+where
+    TX: PinTx<USART>,
+    RX: PinRx<USART>,
+    RTS: PinRts<USART>,
+    CTS: PinCts<USART>,
+{
+}
+
+impl<USART, TX, RX, DE> Pins<USART> for (TX, RX, DE)
+where
+    TX: PinTx<USART>,
+    RX: PinRx<USART>,
+    DE: PinDe<USART>,
+{
+}
+
 /// A filler type for when the Tx pin is unnecessary
 pub struct NoTx;
 /// A filler type for when the Rx pin is unnecessary
 pub struct NoRx;
 /// A filler type for when the Ck pin is unnecessary
 pub struct NoCk;
+/// A filler type for when the Rts pin is unnecessary
+pub struct NoRts;
+/// A filler type for when the Cts pin is unnecessary
+pub struct NoCts;
+/// A filler type for when the De pin is unnecessary
+pub struct NoDe;
 
 macro_rules! usart_pins {
-    ($($USARTX:ty: TX: [$($TX:ty),*] RX: [$($RX:ty),*] CK: [$($CK:ty),*])+) => {
+    ($($USARTX:ty: TX: [NoTx $(, $TX:ty)*] RX: [$($RX:ty),*] CK: [$($CK:ty),*]
+       RTS: [$($RTS:ty),*] CTS: [$($CTS:ty),*] DE: [$($DE:ty),*])+) => {
         $(
+            impl PinTx<$USARTX> for NoTx {}
             $(
                 impl PinTx<$USARTX> for $TX {}
+                impl PinTxHalfDuplex<$USARTX> for $TX {
+                    fn set_open_drain(self) -> Self {
+                        self.set_open_drain()
+                    }
+                }
             )*
             $(
                 impl PinRx<$USARTX> for $RX {}
@@ -187,18 +600,43 @@ macro_rules! usart_pins {
             $(
                 impl PinCk<$USARTX> for $CK {}
             )*
+            $(
+                impl PinRts<$USARTX> for $RTS {}
+            )*
+            $(
+                impl PinCts<$USARTX> for $CTS {}
+            )*
+            $(
+                impl PinDe<$USARTX> for $DE {}
+            )*
         )+
     }
 }
 macro_rules! uart_pins {
-    ($($UARTX:ty: TX: [$($TX:ty),*] RX: [$($RX:ty),*])+) => {
+    ($($UARTX:ty: TX: [NoTx $(, $TX:ty)*] RX: [$($RX:ty),*]
+       RTS: [$($RTS:ty),*] CTS: [$($CTS:ty),*] DE: [$($DE:ty),*])+) => {
         $(
+            impl PinTx<$UARTX> for NoTx {}
             $(
                 impl PinTx<$UARTX> for $TX {}
+                impl PinTxHalfDuplex<$UARTX> for $TX {
+                    fn set_open_drain(self) -> Self {
+                        self.set_open_drain()
+                    }
+                }
             )*
             $(
                 impl PinRx<$UARTX> for $RX {}
             )*
+            $(
+                impl PinRts<$UARTX> for $RTS {}
+            )*
+            $(
+                impl PinCts<$UARTX> for $CTS {}
+            )*
+            $(
+                impl PinDe<$UARTX> for $DE {}
+            )*
         )+
     }
 }
@@ -221,6 +659,18 @@ usart_pins! {
             NoCk,
             PA8<Alternate<AF7>>
         ]
+        RTS: [
+            NoRts,
+            PA12<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PA11<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PA12<Alternate<AF7>>
+        ]
     USART2:
         TX: [
             NoTx,
@@ -237,6 +687,21 @@ usart_pins! {
             PA4<Alternate<AF7>>,
             PD7<Alternate<AF7>>
         ]
+        RTS: [
+            NoRts,
+            PA1<Alternate<AF7>>,
+            PD4<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PA0<Alternate<AF7>>,
+            PD3<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PA1<Alternate<AF7>>,
+            PD4<Alternate<AF7>>
+        ]
     USART3:
         TX: [
             NoTx,
@@ -256,6 +721,21 @@ usart_pins! {
             PC12<Alternate<AF7>>,
             PD10<Alternate<AF7>>
         ]
+        RTS: [
+            NoRts,
+            PB14<Alternate<AF7>>,
+            PD12<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PB13<Alternate<AF7>>,
+            PD11<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PB14<Alternate<AF7>>,
+            PD12<Alternate<AF7>>
+        ]
     USART6:
         TX: [
             NoTx,
@@ -272,6 +752,21 @@ usart_pins! {
             PC8<Alternate<AF7>>,
             PG7<Alternate<AF7>>
         ]
+        RTS: [
+            NoRts,
+            PG8<Alternate<AF7>>,
+            PG12<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PG13<Alternate<AF7>>,
+            PG15<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PG8<Alternate<AF7>>,
+            PG12<Alternate<AF7>>
+        ]
 }
 uart_pins! {
     UART4:
@@ -294,6 +789,21 @@ uart_pins! {
             PH14<Alternate<AF8>>,
             PI9<Alternate<AF8>>
         ]
+        RTS: [
+            NoRts,
+            PA15<Alternate<AF8>>,
+            PB14<Alternate<AF8>>
+        ]
+        CTS: [
+            NoCts,
+            PB0<Alternate<AF8>>,
+            PB15<Alternate<AF8>>
+        ]
+        DE: [
+            NoDe,
+            PA15<Alternate<AF8>>,
+            PB14<Alternate<AF8>>
+        ]
     UART5:
         TX: [
             NoTx,
@@ -307,6 +817,18 @@ uart_pins! {
             PB12<Alternate<AF14>>,
             PD2<Alternate<AF8>>
         ]
+        RTS: [
+            NoRts,
+            PC8<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PC9<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PC8<Alternate<AF7>>
+        ]
     UART7:
         TX: [
             NoTx,
@@ -322,6 +844,21 @@ uart_pins! {
             PE7<Alternate<AF7>>,
             PF6<Alternate<AF7>>
         ]
+        RTS: [
+            NoRts,
+            PE9<Alternate<AF7>>,
+            PF8<Alternate<AF7>>
+        ]
+        CTS: [
+            NoCts,
+            PE10<Alternate<AF7>>,
+            PF9<Alternate<AF7>>
+        ]
+        DE: [
+            NoDe,
+            PE9<Alternate<AF7>>,
+            PF8<Alternate<AF7>>
+        ]
     UART8:
         TX: [
             NoTx,
@@ -333,6 +870,41 @@ uart_pins! {
             PE0<Alternate<AF8>>,
             PJ9<Alternate<AF8>>
         ]
+        RTS: [
+            NoRts,
+            PD15<Alternate<AF8>>
+        ]
+        CTS: [
+            NoCts,
+            PD14<Alternate<AF8>>
+        ]
+        DE: [
+            NoDe,
+            PD15<Alternate<AF8>>
+        ]
+    LPUART1:
+        TX: [
+            NoTx,
+            PA9<Alternate<AF3>>,
+            PB6<Alternate<AF8>>
+        ]
+        RX: [
+            NoRx,
+            PA10<Alternate<AF3>>,
+            PB7<Alternate<AF8>>
+        ]
+        RTS: [
+            NoRts,
+            PA12<Alternate<AF3>>
+        ]
+        CTS: [
+            NoCts,
+            PA11<Alternate<AF3>>
+        ]
+        DE: [
+            NoDe,
+            PA12<Alternate<AF3>>
+        ]
 }
 
 /// Serial abstraction
@@ -368,6 +940,19 @@ pub trait SerialExt<USART>: Sized {
         clocks: &CoreClocks,
     ) -> Result<Serial<USART>, config::InvalidConfig>;
 
+    /// Single-wire half-duplex communication on the Tx pin, which is
+    /// configured as open drain. An external pull-up is usually required.
+    ///
+    /// Transmitted data is also received, and can be discarded by the
+    /// caller.
+    fn serial_half_duplex(
+        self,
+        tx: impl PinTxHalfDuplex<USART>,
+        config: impl Into<config::Config>,
+        prec: Self::Rec,
+        clocks: &CoreClocks,
+    ) -> Result<Serial<USART>, config::InvalidConfig>;
+
     #[deprecated(since = "0.7.0", note = "Deprecated in favour of .serial(..)")]
     fn usart(
         self,
@@ -393,153 +978,1130 @@ pub trait SerialExt<USART>: Sized {
     }
 }
 
-macro_rules! usart {
-    ($(
-        $USARTX:ident: ($usartX:ident, $Rec:ident, $pclkX:ident),
-    )+) => {
-        $(
-            /// Configures a USART peripheral to provide serial
-            /// communication
-            impl Serial<$USARTX> {
-                pub fn $usartX(
-                    usart: $USARTX,
-                    config: impl Into<config::Config>,
-                    prec: rec::$Rec,
-                    clocks: &CoreClocks
-                ) -> Result<Self, config::InvalidConfig>
-                {
-                    use crate::stm32::usart1::cr2::STOP_A as STOP;
-                    use self::config::*;
+// Implementations shared by USART1-8 and LPUART1. The PAC describes the
+// LPUART registers without enumerated values, so only the untyped field
+// writers are used here. Events beyond the common ones are listed with
+// their interrupt enable bits, followed by the events that the peripheral
+// does not support. `$nf` is the noise flag in the ISR register
+macro_rules! usart_common {
+    ($USARTX:ident: ($usartX:ident, $Rec:ident, $nf:ident,
+        [$($XEvent:ident: $xreg:ident.$xie:ident),*],
+        [$($NoEvent:ident),*])) => {
+        impl Serial<$USARTX> {
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                match event {
+                    Event::Rxne => {
+                        self.usart.cr1.modify(|_, w| w.rxneie().set_bit())
+                    },
+                    Event::Txe => {
+                        self.usart.cr1.modify(|_, w| w.txeie().set_bit())
+                    },
+                    Event::Idle => {
+                        self.usart.cr1.modify(|_, w| w.idleie().set_bit())
+                    },
+                    Event::CharacterMatch => {
+                        self.usart.cr1.modify(|_, w| w.cmie().set_bit())
+                    },
+                    $(
+                        Event::$XEvent => {
+                            self.usart.$xreg.modify(|_, w| w.$xie().set_bit())
+                        },
+                    )*
+                    $(
+                        Event::$NoEvent => {},
+                    )*
+                }
+            }
 
-                    let config = config.into();
+            /// Stop listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                match event {
+                    Event::Rxne => {
+                        self.usart.cr1.modify(|_, w| w.rxneie().clear_bit())
+                    },
+                    Event::Txe => {
+                        self.usart.cr1.modify(|_, w| w.txeie().clear_bit())
+                    },
+                    Event::Idle => {
+                        self.usart.cr1.modify(|_, w| w.idleie().clear_bit())
+                    },
+                    Event::CharacterMatch => {
+                        self.usart.cr1.modify(|_, w| w.cmie().clear_bit())
+                    },
+                    $(
+                        Event::$XEvent => {
+                            self.usart.$xreg.modify(|_, w| w.$xie().clear_bit())
+                        },
+                    )*
+                    $(
+                        Event::$NoEvent => {},
+                    )*
+                }
+            }
 
-                    // Enable clock for USART and reset
-                    prec.enable().reset();
+            /// Return true if the line idle status is set
+            pub fn is_idle(& self) -> bool {
+                unsafe { (*$USARTX::ptr()).isr.read().idle().bit_is_set() }
+            }
 
-                    // Get kernel clock
-	                let usart_ker_ck = match Self::kernel_clk(clocks) {
-                        Some(ker_hz) => ker_hz.0,
-                        _ => panic!("$USARTX kernel clock not running!")
-                    };
+            /// Return true if the tx register is empty (and can accept data)
+            pub fn is_txe(& self) -> bool {
+                unsafe { (*$USARTX::ptr()).isr.read().txe().bit_is_set() }
+            }
 
-                    // Prescaler not used for now
-                    let usart_ker_ck_presc = usart_ker_ck;
-                    usart.presc.reset();
+            /// Return true if the rx register is not empty (and can be read)
+            pub fn is_rxne(& self) -> bool {
+                unsafe { (*$USARTX::ptr()).isr.read().rxne().bit_is_set() }
+            }
 
-                    // Calculate baudrate divisor
-                    let usartdiv = usart_ker_ck_presc / config.baudrate.0;
-                    assert!(usartdiv <= 65_536);
+            /// Transmits a break character
+            pub fn send_break(&mut self) {
+                self.usart.rqr.write(|w| w.sbkrq().set_bit());
+            }
 
-                    // 16 times oversampling, OVER8 = 0
-                    let brr = usartdiv as u16;
-                    usart.brr.write(|w| { w.brr().bits(brr) });
+            /// Enables mute mode, in which the receiver discards
+            /// characters until woken by `wakeup`. The receiver enters
+            /// mute mode when `mute` is called, or with address mark
+            /// wakeup when an address mark for another address is
+            /// received.
+            ///
+            /// Address mark wakeup uses the same register as the match
+            /// character, so the two cannot be used together.
+            ///
+            /// The peripheral must be disabled to configure mute mode.
+            /// This method waits for transmission to complete first, but
+            /// a character being received is discarded, and the status
+            /// flags in the ISR register are reset.
+            pub fn enable_mute_mode(&mut self, wakeup: MuteWakeup) {
+                let (address_mark, address_7bit, address) = match wakeup {
+                    MuteWakeup::IdleLine => (false, false, 0),
+                    MuteWakeup::AddressMark4Bit(address) => {
+                        (true, false, address & 0xF)
+                    },
+                    MuteWakeup::AddressMark7Bit(address) => {
+                        (true, true, address & 0x7F)
+                    },
+                };
+
+                // WAKE, ADDM7 and ADD can only be written whilst the
+                // peripheral is disabled
+                while self.usart.isr.read().tc().bit_is_clear() {}
+                self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                // unsafe: any ADD value is valid. The field writer is only
+                // unsafe for the LPUART
+                #[allow(unused_unsafe)]
+                self.usart.cr2.modify(|_, w| unsafe {
+                    w.addm7().bit(address_7bit).add().bits(address)
+                });
+                self.usart.cr1.modify(|_, w| {
+                    w.mme()
+                        .set_bit()
+                        .wake()
+                        .bit(address_mark)
+                        .ue()
+                        .set_bit()
+                });
+            }
 
-                    // disable hardware flow control
-                    // TODO enable DMA
-                    // usart.cr3.write(|w| w.rtse().clear_bit().ctse().clear_bit());
+            /// Disables mute mode
+            pub fn disable_mute_mode(&mut self) {
+                self.usart.cr1.modify(|_, w| w.mme().clear_bit());
+            }
 
-                    // Reset registers to disable advanced USART features
-                    usart.cr2.reset();
-                    usart.cr3.reset();
+            /// Puts the receiver into mute mode, which must have been
+            /// enabled with `enable_mute_mode`
+            pub fn mute(&mut self) {
+                self.usart.rqr.write(|w| w.mmrq().set_bit());
+            }
 
-                    // Set stop bits
-                    usart.cr2.write(|w| {
-                        w.stop().variant(match config.stopbits {
-                            StopBits::STOP0P5 => STOP::STOP0P5,
-                            StopBits::STOP1 => STOP::STOP1,
-                            StopBits::STOP1P5 => STOP::STOP1P5,
-                            StopBits::STOP2 => STOP::STOP2,
-                        })
-                    });
+            /// Return true if the receiver is in mute mode
+            ///
+            /// There is no interrupt for leaving mute mode. The character
+            /// that wakes the receiver, whether an address mark or the
+            /// first character after an idle line, is received and raises
+            /// the `Rxne` event.
+            pub fn is_muted(&self) -> bool {
+                self.usart.isr.read().rwu().bit_is_set()
+            }
 
-                    // Enable transmission and receiving
-                    // and configure frame
-                    usart.cr1.write(|w| {
-                        w.fifoen()
-                            .set_bit() // FIFO mode enabled
-                            .over8()
-                            .oversampling16() // Oversampling by 16
-                            .ue()
-                            .enabled()
-                            .te()
-                            .enabled()
-                            .re()
-                            .enabled()
-                            .m1()
-                            .clear_bit()
-                            .m0()
-                            .variant(match config.wordlength {
-                                WordLength::DataBits8 => M0::BIT8,
-                                WordLength::DataBits9 => M0::BIT9,
-                            }).pce()
-                            .variant(match config.parity {
-                                Parity::ParityNone => PCE::DISABLED,
-                                _ => PCE::ENABLED,
-                            }).ps()
-                            .variant(match config.parity {
-                                Parity::ParityOdd => PS::EVEN,
-                                _ => PS::ODD,
-                            })
-                    });
+            /// Sets the character that raises the character match flag
+            /// when received, for example `b'\n'`. Not available
+            /// with address mark wakeup
+            ///
+            /// The receiver is briefly disabled whilst the character is
+            /// changed, so a character being received at that moment may
+            /// be lost.
+            pub fn set_match_character(&mut self, character: u8) {
+                // ADD can only be written whilst the receiver is
+                // disabled
+                self.usart.cr1.modify(|_, w| w.re().clear_bit());
+                // unsafe: any ADD value is valid. The field writer is only
+                // unsafe for the LPUART
+                #[allow(unused_unsafe)]
+                self.usart.cr2.modify(|_, w| unsafe { w.add().bits(character) });
+                self.usart.cr1.modify(|_, w| w.re().set_bit());
+            }
+
+            /// Return true if the match character has been received
+            pub fn is_character_match(&self) -> bool {
+                self.usart.isr.read().cmf().bit_is_set()
+            }
+
+            /// Clears the character match flag
+            pub fn clear_character_match(&mut self) {
+                self.usart.icr.write(|w| w.cmcf().set_bit());
+            }
+
+            pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+                (
+                    Tx {
+                        _usart: PhantomData,
+                    },
+                    Rx {
+                        _usart: PhantomData,
+                    },
+                )
+            }
+            /// Releases the peripheral
+            pub fn release(self) -> $USARTX {
+                // Wait until both TXFIFO and shift register are empty
+                while self.usart.isr.read().tc().bit_is_clear() {}
+
+                self.usart
+            }
+        }
+
+        impl SerialExt<$USARTX> for $USARTX {
+            type Rec = rec::$Rec;
+
+            fn serial(self,
+                     _pins: impl Pins<$USARTX>,
+                     config: impl Into<config::Config>,
+                     prec: rec::$Rec,
+                     clocks: &CoreClocks
+            ) -> Result<Serial<$USARTX>, config::InvalidConfig>
+            {
+                Serial::$usartX(self, config, prec, clocks)
+            }
+
+            fn serial_unchecked(self,
+                               config: impl Into<config::Config>,
+                               prec: rec::$Rec,
+                               clocks: &CoreClocks
+            ) -> Result<Serial<$USARTX>, config::InvalidConfig>
+            {
+                Serial::$usartX(self, config, prec, clocks)
+            }
+
+            fn serial_half_duplex(self,
+                                  tx: impl PinTxHalfDuplex<$USARTX>,
+                                  config: impl Into<config::Config>,
+                                  prec: rec::$Rec,
+                                  clocks: &CoreClocks
+            ) -> Result<Serial<$USARTX>, config::InvalidConfig>
+            {
+                let _ = tx.set_open_drain();
+                let serial = Serial::$usartX(self, config, prec, clocks)?;
+
+                // HDSEL can only be written whilst the peripheral is
+                // disabled
+                serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                serial.usart.cr3.modify(|_, w| w.hdsel().set_bit());
+                serial.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                Ok(serial)
+            }
+        }
+
+        impl serial::Read<u8> for Serial<$USARTX> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                let mut rx: Rx<$USARTX> = Rx {
+                    _usart: PhantomData,
+                };
+                rx.read()
+            }
+        }
+
+        impl serial::Read<u16> for Serial<$USARTX> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u16, Error> {
+                let mut rx: Rx<$USARTX> = Rx {
+                    _usart: PhantomData,
+                };
+                rx.read()
+            }
+        }
+
+        impl serial::Read<u8> for Rx<$USARTX> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                serial::Read::<u16>::read(self).map(|word| word as u8)
+            }
+        }
+
+        impl serial::Read<u16> for Rx<$USARTX> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u16, Error> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                Err(if isr.pe().bit_is_set() {
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.pecf().set_bit() );};
+                    nb::Error::Other(Error::Parity)
+                } else if isr.fe().bit_is_set() {
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.fecf().set_bit() );};
+                    nb::Error::Other(Error::Framing)
+                } else if isr.$nf().bit_is_set() {
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.ncf().set_bit() );};
+                    nb::Error::Other(Error::Noise)
+                } else if isr.ore().bit_is_set() {
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.orecf().set_bit() );};
+                    nb::Error::Other(Error::Overrun)
+                } else if isr.rxne().bit_is_set() {
+                    // NOTE(unsafe) atomic read, RDR only accessed by Rx
+                    // part
+                    return Ok(unsafe { (*$USARTX::ptr()).rdr.read().rdr().bits() });
+                } else {
+                    nb::Error::WouldBlock
+                })
+            }
+        }
+
+        impl Rx<$USARTX> {
+            /// Start listening for `Rxne` event
+            pub fn listen(&mut self) {
+                // unsafe: rxneie bit accessed by Rx part only
+                unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.rxneie().set_bit());
+            }
+
+            /// Stop listening for `Rxne` event
+            pub fn unlisten(&mut self) {
+                // unsafe: rxneie bit accessed by Rx part only
+                unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.rxneie().clear_bit());
+            }
+        }
+
+        impl serial::Write<u8> for Serial<$USARTX> {
+            type Error = Never;
+
+            fn flush(&mut self) -> nb::Result<(), Never> {
+                let mut tx: Tx<$USARTX> = Tx {
+                    _usart: PhantomData,
+                };
+                serial::Write::<u8>::flush(&mut tx)
+            }
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Never> {
+                let mut tx: Tx<$USARTX> = Tx {
+                    _usart: PhantomData,
+                };
+                tx.write(byte)
+            }
+        }
+
+        impl serial::Write<u16> for Serial<$USARTX> {
+            type Error = Never;
+
+            fn flush(&mut self) -> nb::Result<(), Never> {
+                let mut tx: Tx<$USARTX> = Tx {
+                    _usart: PhantomData,
+                };
+                serial::Write::<u16>::flush(&mut tx)
+            }
+
+            fn write(&mut self, word: u16) -> nb::Result<(), Never> {
+                let mut tx: Tx<$USARTX> = Tx {
+                    _usart: PhantomData,
+                };
+                tx.write(word)
+            }
+        }
+
+        impl serial_block::write::Default<u8> for Serial<$USARTX> {
+            //implement marker trait to opt-in to default blocking write implementation
+        }
+
+        impl serial_block::write::Default<u16> for Serial<$USARTX> {}
+
+        impl serial::Write<u8> for Tx<$USARTX> {
+            // NOTE(Void) See section "29.7 USART interrupts"; the
+            // only possible errors during transmission are: clear
+            // to send errors (CTS only pauses transmission when
+            // hardware flow control is enabled) and framing errors
+            // (which only occur in SmartCard mode); neither of these
+            // apply to our hardware configuration
+            type Error = Never;
+
+            fn flush(&mut self) -> nb::Result<(), Never> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                if isr.tc().bit_is_set() {
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Never> {
+                serial::Write::<u16>::write(self, u16::from(byte))
+            }
+        }
+
+        impl serial::Write<u16> for Tx<$USARTX> {
+            type Error = Never;
+
+            fn flush(&mut self) -> nb::Result<(), Never> {
+                serial::Write::<u8>::flush(self)
+            }
+
+            fn write(&mut self, word: u16) -> nb::Result<(), Never> {
+                // NOTE(unsafe) atomic read with no side effects
+                let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                if isr.txe().bit_is_set() {
+                    // NOTE(unsafe) atomic write to stateless register
+                    unsafe {
+                        (*$USARTX::ptr()).tdr.write(|w| w.tdr().bits(word))
+                    }
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+
+        impl Tx<$USARTX> {
+            /// Start listening for `Txe` event
+            pub fn listen(&mut self) {
+                // unsafe: txeie bit accessed by Tx part only
+                unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.txeie().set_bit());
+            }
+
+            /// Stop listening for `Txe` event
+            pub fn unlisten(&mut self) {
+                // unsafe: txeie bit accessed by Tx part only
+                unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.txeie().clear_bit());
+            }
+        }
+    }
+}
+
+macro_rules! usart {
+    ($(
+        $USARTX:ident: ($usartX:ident, $Rec:ident, $pclkX:ident),
+    )+) => {
+        $(
+            usart_common! {
+                $USARTX: ($usartX, $Rec, nf,
+                    [LinBreak: cr2.lbdie, ReceiverTimeout: cr1.rtoie], [])
+            }
+
+            /// Configures a USART peripheral to provide serial
+            /// communication
+            impl Serial<$USARTX> {
+                pub fn $usartX(
+                    usart: $USARTX,
+                    config: impl Into<config::Config>,
+                    prec: rec::$Rec,
+                    clocks: &CoreClocks
+                ) -> Result<Self, config::InvalidConfig>
+                {
+                    use crate::stm32::usart1::cr2::ABRMOD_A as ABRMOD;
+                    use crate::stm32::usart1::cr2::STOP_A as STOP;
+                    use self::config::*;
+
+                    let config = config.into();
+
+                    // Enable clock for USART and reset
+                    prec.enable().reset();
+
+                    // Get kernel clock
+	                let usart_ker_ck = match Self::kernel_clk(clocks) {
+                        Some(ker_hz) => ker_hz.0,
+                        _ => panic!("$USARTX kernel clock not running!")
+                    };
+
+                    // LIN mode requires 1 stop bit
+                    if config.lin {
+                        match config.stopbits {
+                            StopBits::STOP1 => {},
+                            _ => return Err(InvalidConfig::Unsupported),
+                        }
+                    }
+
+                    // Calculate prescaler, oversampling and baudrate
+                    // divisor. LIN mode only supports 16x oversampling
+                    let (presc, over8, brr) =
+                        usart_brr(usart_ker_ck, config.baudrate.0, !config.lin)?;
+
+                    // Driver enable times are counted in sample times,
+                    // which are 1/16 bit time with 16x oversampling and
+                    // 1/8 bit time with 8x oversampling. They are rounded
+                    // up, and limited to 31 sample times
+                    let de_samples = |time: BitTime| {
+                        let sixteenths = u32::from(time.as_sixteenths());
+                        if over8 {
+                            sixteenths / 2 + sixteenths % 2
+                        } else {
+                            sixteenths
+                        }
+                    };
+                    let deat = de_samples(config.de_assertion_time);
+                    let dedt = de_samples(config.de_deassertion_time);
+                    if deat > 31 || dedt > 31 {
+                        return Err(InvalidConfig::Unsupported);
+                    }
+
+                    // unsafe: presc is an index into PRESC_DIVISIONS
+                    usart.presc.write(|w| unsafe { w.prescaler().bits(presc) });
+                    usart.brr.write(|w| { w.brr().bits(brr) });
+
+                    // Reset registers to disable advanced USART features
+                    // and DMA requests
+                    usart.cr2.reset();
+                    usart.cr3.reset();
+
+                    // Hardware flow control and RS-485 driver enable
+                    let (rts_cts, de) = match config.flowcontrol {
+                        FlowControl::FlowControlNone => (false, false),
+                        FlowControl::FlowControlRtsCts => (true, false),
+                        FlowControl::FlowControlRs485 => (false, true),
+                    };
+                    let de_active_low = match config.depolarity {
+                        DePolarity::DePolarityHigh => false,
+                        DePolarity::DePolarityLow => true,
+                    };
+                    usart.cr3.write(|w| {
+                        w.rtse()
+                            .bit(rts_cts)
+                            .ctse()
+                            .bit(rts_cts)
+                            .dem()
+                            .bit(de)
+                            .dep()
+                            .bit(de_active_low)
+                    });
+
+                    // Set stop bits, LIN mode, pin swap, inversion and bit order
+                    usart.cr2.write(|w| {
+                        w.stop().variant(match config.stopbits {
+                            StopBits::STOP0P5 => STOP::STOP0P5,
+                            StopBits::STOP1 => STOP::STOP1,
+                            StopBits::STOP1P5 => STOP::STOP1P5,
+                            StopBits::STOP2 => STOP::STOP2,
+                        })
+                        .linen()
+                        .bit(config.lin)
+                        .lbdl()
+                        .bit(match config.lin_break_length {
+                            BreakLength::Break10 => false,
+                            BreakLength::Break11 => true,
+                        })
+                        .abren()
+                        .bit(match config.autobaud {
+                            AutoBaud::AutoBaudDisabled => false,
+                            _ => true,
+                        })
+                        .abrmod()
+                        .variant(match config.autobaud {
+                            AutoBaud::AutoBaudFallingEdge => ABRMOD::EDGE,
+                            AutoBaud::AutoBaud0x7F => ABRMOD::FRAME7F,
+                            AutoBaud::AutoBaud0x55 => ABRMOD::FRAME55,
+                            _ => ABRMOD::START,
+                        })
+                        .swap()
+                        .bit(config.swap_tx_rx)
+                        .rxinv()
+                        .bit(config.invert_rx)
+                        .txinv()
+                        .bit(config.invert_tx)
+                        .datainv()
+                        .bit(config.invert_data)
+                        .msbfirst()
+                        .bit(config.msb_first)
+                    });
+
+                    // Enable transmission and receiving
+                    // and configure frame
+                    usart.cr1.write(|w| {
+                        w.fifoen()
+                            .set_bit() // FIFO mode enabled
+                            .over8()
+                            .bit(over8)
+                            .ue()
+                            .enabled()
+                            .te()
+                            .enabled()
+                            .re()
+                            .enabled()
+                            .deat()
+                            .bits(deat as u8)
+                            .dedt()
+                            .bits(dedt as u8)
+                            .m1()
+                            .variant(match config.wordlength {
+                                WordLength::DataBits7 => M1::BIT7,
+                                _ => M1::M0,
+                            })
+                            .m0()
+                            .variant(match config.wordlength {
+                                WordLength::DataBits9 => M0::BIT9,
+                                _ => M0::BIT8,
+                            }).pce()
+                            .variant(match config.parity {
+                                Parity::ParityNone => PCE::DISABLED,
+                                _ => PCE::ENABLED,
+                            }).ps()
+                            .variant(match config.parity {
+                                Parity::ParityOdd => PS::EVEN,
+                                _ => PS::ODD,
+                            })
+                    });
 
                     Ok(Serial { usart })
                 }
 
-                /// Starts listening for an interrupt event
-                pub fn listen(&mut self, event: Event) {
-                    match event {
-                        Event::Rxne => {
-                            self.usart.cr1.modify(|_, w| w.rxneie().enabled())
-                        },
-                        Event::Txe => {
-                            self.usart.cr1.modify(|_, w| w.txeie().enabled())
-                        },
-                        Event::Idle => {
-                            self.usart.cr1.modify(|_, w| w.idleie().enabled())
-                        },
+                /// Returns the detected baudrate once automatic baudrate
+                /// detection has completed.
+                ///
+                /// Returns `WouldBlock` whilst detection is in progress,
+                /// and `Error::AutoBaud` if it failed. The character used
+                /// for detection is also received.
+                pub fn detected_baudrate(
+                    &self,
+                    clocks: &CoreClocks,
+                ) -> nb::Result<Hertz, Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.abre().bit_is_set() {
+                        Err(nb::Error::Other(Error::AutoBaud))
+                    } else if isr.abrf().bit_is_set() {
+                        let usart_ker_ck = match Self::kernel_clk(clocks) {
+                            Some(ker_hz) => ker_hz.0,
+                            _ => panic!("$USARTX kernel clock not running!")
+                        };
+                        let presc = self.usart.presc.read().prescaler().bits();
+                        let usart_ker_ck_presc =
+                            usart_ker_ck / PRESC_DIVISIONS[presc as usize];
+
+                        // With 8x oversampling, BRR[2:0] is USARTDIV[3:0]
+                        // shifted right by one bit
+                        let brr = u32::from(self.usart.brr.read().brr().bits());
+                        let (usartdiv, multiplier) =
+                            if self.usart.cr1.read().over8().bit_is_set() {
+                                ((brr & !0xF) | ((brr & 0x7) << 1), 2)
+                            } else {
+                                (brr, 1)
+                            };
+
+                        Ok(Hertz(multiplier * usart_ker_ck_presc / usartdiv))
+                    } else {
+                        Err(nb::Error::WouldBlock)
                     }
                 }
 
-                /// Stop listening for an interrupt event
-                pub fn unlisten(&mut self, event: Event) {
-                    match event {
-                        Event::Rxne => {
-                            self.usart.cr1.modify(|_, w| w.rxneie().disabled())
-                        },
-                        Event::Txe => {
-                            self.usart.cr1.modify(|_, w| w.txeie().disabled())
-                        },
-                        Event::Idle => {
-                            self.usart.cr1.modify(|_, w| w.idleie().disabled())
-                        },
+                /// Restarts automatic baudrate detection on the next
+                /// received character
+                pub fn restart_autobaud(&mut self) {
+                    self.usart.rqr.write(|w| w.abrrq().request());
+                }
+
+                /// Return true if a LIN break has been detected
+                pub fn is_lin_break(&self) -> bool {
+                    self.usart.isr.read().lbdf().bit_is_set()
+                }
+
+                /// Clears the LIN break detection flag
+                pub fn clear_lin_break(&mut self) {
+                    self.usart.icr.write(|w| w.lbdcf().clear());
+                }
+
+                /// Transmits a LIN header for frame `id`: a break, the sync
+                /// field and the protected identifier. Blocks until the
+                /// header has been transmitted.
+                ///
+                /// The header echoed by the LIN transceiver is discarded.
+                pub fn lin_write_header(&mut self, id: u8) {
+                    // Wait for any previous transmission to complete
+                    while self.usart.isr.read().tc().bit_is_clear() {}
+
+                    self.send_break();
+                    block!(serial::Write::<u8>::write(self, 0x55)).ok();
+                    block!(serial::Write::<u8>::write(self, lin::pid(id))).ok();
+
+                    self.lin_discard_echo();
+                }
+
+                /// Transmits a LIN response for frame `id`: the `data`
+                /// followed by its checksum. Blocks until the response has
+                /// been transmitted.
+                ///
+                /// The response echoed by the LIN transceiver is discarded.
+                pub fn lin_write_response(
+                    &mut self,
+                    id: u8,
+                    data: &[u8],
+                    checksum: lin::Checksum,
+                ) {
+                    for byte in data {
+                        block!(serial::Write::<u8>::write(self, *byte)).ok();
+                    }
+                    let checksum = lin::checksum(checksum, lin::pid(id), data);
+                    block!(serial::Write::<u8>::write(self, checksum)).ok();
+
+                    self.lin_discard_echo();
+                }
+
+                /// Receives a LIN header, returning the frame identifier.
+                ///
+                /// Blocks until a break has been detected, and then until
+                /// the sync field and protected identifier have been
+                /// received. To avoid blocking whilst the bus is idle, call
+                /// this once `is_lin_break` returns true or the `LinBreak`
+                /// event has occurred. A protected identifier with
+                /// incorrect parity returns `Error::Parity`, and an
+                /// incorrect sync field returns `Error::Framing`.
+                pub fn lin_read_header(&mut self) -> Result<u8, Error> {
+                    while !self.is_lin_break() {}
+
+                    // Discard the break character
+                    self.usart.rqr.write(|w| w.rxfrq().discard());
+                    self.usart.icr.write(|w| {
+                        w.fecf().clear().lbdcf().clear()
+                    });
+
+                    let sync = block!(serial::Read::<u8>::read(self))?;
+                    if sync != 0x55 {
+                        return Err(Error::Framing);
+                    }
+
+                    let pid = block!(serial::Read::<u8>::read(self))?;
+                    let id = pid & 0x3F;
+                    if lin::pid(id) != pid {
+                        return Err(Error::Parity);
                     }
+
+                    Ok(id)
                 }
 
+                /// Receives a LIN response for frame `id` into `buffer`,
+                /// and verifies its checksum. Blocks until `buffer` and the
+                /// checksum have been received.
+                pub fn lin_read_response(
+                    &mut self,
+                    id: u8,
+                    buffer: &mut [u8],
+                    checksum: lin::Checksum,
+                ) -> Result<(), Error> {
+                    for byte in buffer.iter_mut() {
+                        *byte = block!(serial::Read::<u8>::read(self))?;
+                    }
+                    let received = block!(serial::Read::<u8>::read(self))?;
+
+                    if received == lin::checksum(checksum, lin::pid(id), buffer) {
+                        Ok(())
+                    } else {
+                        Err(Error::Checksum)
+                    }
+                }
+
+                /// Waits for transmission to complete, and then discards
+                /// everything received in the meantime
+                fn lin_discard_echo(&mut self) {
+                    while self.usart.isr.read().tc().bit_is_clear() {}
+
+                    self.usart.rqr.write(|w| w.rxfrq().discard());
+                    self.usart.icr.write(|w| {
+                        w.pecf()
+                            .clear()
+                            .fecf()
+                            .clear()
+                            .ncf()
+                            .clear()
+                            .orecf()
+                            .clear()
+                            .lbdcf()
+                            .clear()
+                    });
+                }
+            }
+
+            impl Rx<$USARTX> {
                 /// Return true if the line idle status is set
-                pub fn is_idle(& self) -> bool {
+                pub fn is_idle(&self) -> bool {
+                    // unsafe: atomic read with no side effects
                     unsafe { (*$USARTX::ptr()).isr.read().idle().bit_is_set() }
                 }
 
-                /// Return true if the tx register is empty (and can accept data)
-                pub fn is_txe(& self) -> bool {
-                    unsafe { (*$USARTX::ptr()).isr.read().txe().bit_is_set() }
+                /// Clears the idle line flag
+                pub fn clear_idle(&mut self) {
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.idlecf().clear());
                 }
 
-                /// Return true if the rx register is not empty (and can be read)
-                pub fn is_rxne(& self) -> bool {
-                    unsafe { (*$USARTX::ptr()).isr.read().rxne().bit_is_set() }
+                /// Enables the receiver timeout. The timeout expires when
+                /// the line has been idle for `bit_times` bit durations
+                /// after the last received character.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bit_times` is larger than 0xFF_FFFF
+                pub fn enable_receiver_timeout(&mut self, bit_times: u32) {
+                    assert!(bit_times <= 0xFF_FFFF);
+
+                    // unsafe: rtor register and rtoen bit accessed by Rx
+                    // part only
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    usart.rtor.modify(|_, w| w.rto().bits(bit_times));
+                    usart.cr2.modify(|_, w| w.rtoen().enabled());
                 }
 
-                pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
-                    (
-                        Tx {
-                            _usart: PhantomData,
-                        },
-                        Rx {
-                            _usart: PhantomData,
-                        },
-                    )
+                /// Disables the receiver timeout
+                pub fn disable_receiver_timeout(&mut self) {
+                    // unsafe: rtoen bit accessed by Rx part only
+                    unsafe { &*$USARTX::ptr() }.cr2.modify(|_, w| w.rtoen().disabled());
+                }
+
+                /// Return true if the receiver timeout has expired
+                pub fn is_receiver_timeout(&self) -> bool {
+                    // unsafe: atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().rtof().bit_is_set() }
+                }
+
+                /// Clears the receiver timeout flag
+                pub fn clear_receiver_timeout(&mut self) {
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.rtocf().clear());
+                }
+
+                /// Receives a frame into `buffer`, returning the number of
+                /// bytes received.
+                ///
+                /// Blocks until at least one byte has been received, and
+                /// then until the end of the frame. The end of the frame
+                /// is the receiver timeout if that is enabled, and
+                /// otherwise an idle line. If `buffer` fills before the end
+                /// of the frame, the remaining bytes are left for the next
+                /// call.
+                ///
+                /// The end of frame flag is not cleared on entry, so a
+                /// frame that was received before the call ends once its
+                /// bytes have been read. A flag that is set before any
+                /// byte has been received belongs to a frame that has
+                /// already been read, and is cleared.
+                pub fn read_frame(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+                    // unsafe: atomic read with no side effects, and atomic
+                    // write to stateless register
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let timeout = usart.cr2.read().rtoen().is_enabled();
+                    let mut count = 0;
+
+                    while count < buffer.len() {
+                        match self.read() {
+                            Ok(byte) => {
+                                buffer[count] = byte;
+                                count += 1;
+                            }
+                            Err(nb::Error::WouldBlock) => {
+                                // All received bytes have been read, so a
+                                // flag that is set marks the end of the
+                                // frame
+                                let isr = usart.isr.read();
+                                let end = if timeout {
+                                    isr.rtof().bit_is_set()
+                                } else {
+                                    isr.idle().bit_is_set()
+                                };
+
+                                if end {
+                                    usart.icr.write(|w| if timeout {
+                                        w.rtocf().clear()
+                                    } else {
+                                        w.idlecf().clear()
+                                    });
+
+                                    if count > 0 {
+                                        break;
+                                    }
+                                }
+                            }
+                            Err(nb::Error::Other(e)) => return Err(e),
+                        }
+                    }
+
+                    Ok(count)
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! usart_dma {
+    ($($USARTX:ident,)+) => {
+        $(
+            paste::item! {
+                unsafe impl TargetAddress<MemoryToPeripheral> for Tx<$USARTX> {
+                    type MemSize = u8;
+                    type Mux = DMAMUX1;
+
+                    const REQUEST_LINE: Option<u8> =
+                        Some(DMAReq::[<$USARTX _TX_DMA>] as u8);
+
+                    fn address(&self) -> usize {
+                        // unsafe: only the address is taken
+                        unsafe { &(*$USARTX::ptr()).tdr as *const _ as usize }
+                    }
+                }
+
+                unsafe impl TargetAddress<PeripheralToMemory> for Rx<$USARTX> {
+                    type MemSize = u8;
+                    type Mux = DMAMUX1;
+
+                    const REQUEST_LINE: Option<u8> =
+                        Some(DMAReq::[<$USARTX _RX_DMA>] as u8);
+
+                    fn address(&self) -> usize {
+                        // unsafe: only the address is taken
+                        unsafe { &(*$USARTX::ptr()).rdr as *const _ as usize }
+                    }
+                }
+            }
+
+            impl Tx<$USARTX> {
+                /// Enable DMA requests from the transmitter (DMAT)
+                pub fn enable_dma(&mut self) {
+                    // unsafe: dmat bit accessed by Tx part only
+                    unsafe { &*$USARTX::ptr() }.cr3.modify(|_, w| w.dmat().enabled());
+                }
+
+                /// Disable DMA requests from the transmitter (DMAT)
+                pub fn disable_dma(&mut self) {
+                    // unsafe: dmat bit accessed by Tx part only
+                    unsafe { &*$USARTX::ptr() }.cr3.modify(|_, w| w.dmat().disabled());
+                }
+
+                /// Transmits all of `buf` using the DMA `stream`. The
+                /// transfer is started before this method returns.
+                ///
+                /// The transfer is complete when its transfer complete
+                /// flag is set. The last byte may still be in the
+                /// transmitter at that point. End the transfer with
+                /// `free_tx`, which also disables DMA requests from the
+                /// transmitter.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the buffer is longer than 65535 bytes
+                pub fn write_all_dma<STREAM, BUF>(
+                    self,
+                    stream: STREAM,
+                    buf: BUF,
+                    config: STREAM::Config,
+                ) -> Transfer<STREAM, Self, MemoryToPeripheral, BUF>
+                where
+                    STREAM: Stream<Mux = DMAMUX1> + MemoryAccess<BUF>,
+                    BUF: StaticReadBuffer<Word = u8>,
+                {
+                    let mut transfer = Transfer::<_, _, MemoryToPeripheral, _>::init(
+                        stream, self, buf, config,
+                    );
+                    transfer.start(|tx| tx.enable_dma());
+                    transfer
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Enable DMA requests from the receiver (DMAR)
+                pub fn enable_dma(&mut self) {
+                    // unsafe: dmar bit accessed by Rx part only
+                    unsafe { &*$USARTX::ptr() }.cr3.modify(|_, w| w.dmar().enabled());
+                }
+
+                /// Disable DMA requests from the receiver (DMAR)
+                pub fn disable_dma(&mut self) {
+                    // unsafe: dmar bit accessed by Rx part only
+                    unsafe { &*$USARTX::ptr() }.cr3.modify(|_, w| w.dmar().disabled());
+                }
+
+                /// Receives into `buf` using the DMA `stream`. The
+                /// transfer is started before this method returns.
+                ///
+                /// The transfer completes when `buf` is full. For
+                /// variable-length frames, wait for an idle line instead
+                /// and end the transfer with `free_idle`.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the buffer is longer than 65535 bytes
+                pub fn read_dma<STREAM, BUF>(
+                    self,
+                    stream: STREAM,
+                    buf: BUF,
+                    config: STREAM::Config,
+                ) -> Transfer<STREAM, Self, PeripheralToMemory, BUF>
+                where
+                    STREAM: Stream<Mux = DMAMUX1> + MemoryAccess<BUF>,
+                    BUF: StaticWriteBuffer<Word = u8>,
+                {
+                    // Only detect idle lines from now on
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.idlecf().clear());
+
+                    let mut transfer = Transfer::<_, _, PeripheralToMemory, _>::init(
+                        stream, self, buf, config,
+                    );
+                    transfer.start(|rx| rx.enable_dma());
+                    transfer
+                }
+            }
+
+            impl<STREAM, BUF> Transfer<STREAM, Tx<$USARTX>, MemoryToPeripheral, BUF>
+            where
+                STREAM: Stream<Mux = DMAMUX1>,
+                BUF: StaticReadBuffer<Word = u8>,
+            {
+                /// Stops the transfer, and returns the stream, the
+                /// transmitter and the buffer.
+                ///
+                /// DMA requests from the transmitter are disabled.
+                pub fn free_tx(self) -> (STREAM, Tx<$USARTX>, BUF) {
+                    let (stream, mut tx, buf) = self.free();
+                    tx.disable_dma();
+
+                    (stream, tx, buf)
+                }
+            }
+
+            impl<STREAM, BUF> Transfer<STREAM, Rx<$USARTX>, PeripheralToMemory, BUF>
+            where
+                STREAM: Stream<Mux = DMAMUX1>,
+                BUF: StaticWriteBuffer<Word = u8>,
+            {
+                /// Returns true if the line has become idle after
+                /// receiving at least one byte, indicating the end of a
+                /// frame
+                pub fn is_idle(&self) -> bool {
+                    // unsafe: atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().idle().bit_is_set() }
+                }
+
+                /// Clears the idle line flag
+                pub fn clear_idle(&mut self) {
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.idlecf().clear());
+                }
+
+                /// Start listening for the `Idle` event
+                pub fn listen_idle(&mut self) {
+                    // unsafe: idleie bit accessed by Rx part only
+                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.idleie().enabled());
+                }
+
+                /// Stop listening for the `Idle` event
+                pub fn unlisten_idle(&mut self) {
+                    // unsafe: idleie bit accessed by Rx part only
+                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.idleie().disabled());
+                }
+
+                /// Stops the transfer, typically after an idle line, and
+                /// returns the stream, the receiver, the buffer and the
+                /// number of bytes that were received into the buffer.
+                ///
+                /// DMA requests from the receiver are disabled.
+                pub fn free_idle(self) -> (STREAM, Rx<$USARTX>, BUF, usize) {
+                    let (stream, mut rx, mut buf) = self.free();
+                    rx.disable_dma();
+
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.idlecf().clear());
+
+                    let (_, len) = buf.static_write_buffer();
+                    let remaining = STREAM::get_number_of_transfers() as usize;
+                    (stream, rx, buf, len - remaining)
+                }
+            }
+        )+
+    }
+}
+
+/// Serial synchronous master, which drives the Ck pin and implements
+/// the SPI traits
+pub struct SerialSync<USART> {
+    usart: USART,
+}
+
+pub trait SerialSyncExt<USART>: Sized {
+    type Rec: ResetEnable;
+
+    /// Configures the USART as a synchronous master, clocking each bit
+    /// on the Ck pin. The clock idles when no data is being transmitted,
+    /// and a clock pulse is output for the last data bit.
+    ///
+    /// The baudrate, word length, parity, stop bits and bit order are
+    /// taken from `config`. Data is transmitted LSB first, unless
+    /// `config` selects MSB first.
+    fn serial_sync<TX, RX, CK>(
+        self,
+        _pins: (TX, RX, CK),
+        mode: spi::Mode,
+        config: impl Into<config::Config>,
+        prec: Self::Rec,
+        clocks: &CoreClocks,
+    ) -> Result<SerialSync<USART>, config::InvalidConfig>
+    where
+        TX: PinTx<USART>,
+        RX: PinRx<USART>,
+        CK: PinCk<USART>;
+}
+
+macro_rules! usart_sync {
+    ($($USARTX:ident: ($usartX:ident, $Rec:ident),)+) => {
+        $(
+            impl SerialSyncExt<$USARTX> for $USARTX {
+                type Rec = rec::$Rec;
+
+                fn serial_sync<TX, RX, CK>(
+                    self,
+                    _pins: (TX, RX, CK),
+                    mode: spi::Mode,
+                    config: impl Into<config::Config>,
+                    prec: rec::$Rec,
+                    clocks: &CoreClocks,
+                ) -> Result<SerialSync<$USARTX>, config::InvalidConfig>
+                where
+                    TX: PinTx<$USARTX>,
+                    RX: PinRx<$USARTX>,
+                    CK: PinCk<$USARTX>,
+                {
+                    let Serial { usart } = Serial::$usartX(self, config, prec, clocks)?;
+
+                    // CLKEN, CPOL, CPHA and LBCL can only be written
+                    // whilst the USART is disabled
+                    usart.cr1.modify(|_, w| w.ue().disabled());
+                    usart.cr2.modify(|_, w| {
+                        w.clken()
+                            .set_bit()
+                            .cpol()
+                            .bit(mode.polarity == spi::Polarity::IdleHigh)
+                            .cpha()
+                            .bit(mode.phase == spi::Phase::CaptureOnSecondTransition)
+                            .lbcl()
+                            .set_bit()
+                    });
+                    usart.cr1.modify(|_, w| w.ue().enabled());
+
+                    Ok(SerialSync { usart })
                 }
+            }
+
+            impl SerialSync<$USARTX> {
                 /// Releases the USART peripheral
                 pub fn release(self) -> $USARTX {
                     // Wait until both TXFIFO and shift register are empty
@@ -549,32 +2111,178 @@ macro_rules! usart {
                 }
             }
 
-            impl SerialExt<$USARTX> for $USARTX {
+            impl spi::FullDuplex<u8> for SerialSync<$USARTX> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    let mut rx: Rx<$USARTX> = Rx {
+                        _usart: PhantomData,
+                    };
+                    rx.read()
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    tx.write(byte).map_err(|e| match e {
+                        nb::Error::WouldBlock => nb::Error::WouldBlock,
+                        nb::Error::Other(never) => match never {},
+                    })
+                }
+            }
+
+            impl embedded_hal::blocking::spi::transfer::Default<u8>
+                for SerialSync<$USARTX> {}
+
+            impl embedded_hal::blocking::spi::write::Default<u8>
+                for SerialSync<$USARTX> {}
+        )+
+    }
+}
+
+/// Serial smartcard interface, for ISO 7816-3 T=0 communication
+///
+/// The Tx pin is the bidirectional I/O line to the card, and the Ck pin
+/// supplies the card clock. Received characters with parity errors are
+/// NACKed and retried by the card, and transmitted characters NACKed by
+/// the card are retried by the USART, up to the configured number of
+/// retries.
+pub struct Smartcard<USART> {
+    usart: USART,
+}
+
+pub trait SmartcardExt<USART>: Sized {
+    type Rec: ResetEnable;
+
+    /// Configures the USART for smartcard communication. The Tx pin is
+    /// configured as open drain.
+    fn smartcard<TX, CK>(
+        self,
+        pins: (TX, CK),
+        config: config::SmartcardConfig,
+        prec: Self::Rec,
+        clocks: &CoreClocks,
+    ) -> Result<Smartcard<USART>, config::InvalidConfig>
+    where
+        TX: PinTxHalfDuplex<USART>,
+        CK: PinCk<USART>;
+}
+
+macro_rules! usart_smartcard {
+    ($($USARTX:ident: ($usartX:ident, $Rec:ident),)+) => {
+        $(
+            impl SmartcardExt<$USARTX> for $USARTX {
                 type Rec = rec::$Rec;
 
-                fn serial(self,
-                         _pins: impl Pins<$USARTX>,
-                         config: impl Into<config::Config>,
-                         prec: rec::$Rec,
-                         clocks: &CoreClocks
-                ) -> Result<Serial<$USARTX>, config::InvalidConfig>
+                fn smartcard<TX, CK>(
+                    self,
+                    pins: (TX, CK),
+                    config: config::SmartcardConfig,
+                    prec: rec::$Rec,
+                    clocks: &CoreClocks,
+                ) -> Result<Smartcard<$USARTX>, config::InvalidConfig>
+                where
+                    TX: PinTxHalfDuplex<$USARTX>,
+                    CK: PinCk<$USARTX>,
                 {
-                    Serial::$usartX(self, config, prec, clocks)
+                    use self::config::*;
+
+                    let _ = pins.0.set_open_drain();
+
+                    if config.retries > 7 {
+                        return Err(InvalidConfig::Unsupported);
+                    }
+
+                    // Enable clock for USART and reset
+                    prec.enable().reset();
+
+                    // Get kernel clock
+                    let usart_ker_ck = match Serial::<$USARTX>::kernel_clk(clocks) {
+                        Some(ker_hz) => ker_hz.0,
+                        _ => panic!("$USARTX kernel clock not running!")
+                    };
+
+                    // Calculate prescaler and baudrate divisor. Smartcard
+                    // mode only supports 16x oversampling
+                    let (presc, _, brr) =
+                        usart_brr(usart_ker_ck, config.baudrate.0, false)?;
+
+                    // The card clock is the prescaled kernel clock divided
+                    // by 2 * PSC
+                    let usart_ker_ck_presc =
+                        usart_ker_ck / PRESC_DIVISIONS[presc as usize];
+                    let clock = 2 * config.clock.0;
+                    let psc = (usart_ker_ck_presc + clock / 2) / clock;
+                    if !(1..=31).contains(&psc) {
+                        return Err(InvalidConfig::Unsupported);
+                    }
+
+                    let usart = self;
+
+                    // unsafe: presc is an index into PRESC_DIVISIONS
+                    usart.presc.write(|w| unsafe { w.prescaler().bits(presc) });
+                    usart.brr.write(|w| { w.brr().bits(brr) });
+                    usart.gtpr.write(|w| {
+                        w.gt().bits(config.guard_time).psc().bits(psc as u8)
+                    });
+
+                    // 1.5 stop bits, and the card clock on the Ck pin
+                    usart.cr2.write(|w| w.stop().stop1p5().clken().enabled());
+                    usart.cr3.write(|w| {
+                        w.scarcnt()
+                            .bits(config.retries)
+                            .nack()
+                            .bit(config.nack)
+                            .scen()
+                            .enabled()
+                    });
+
+                    // 8 data bits with even parity
+                    usart.cr1.write(|w| {
+                        w.fifoen()
+                            .set_bit() // FIFO mode enabled
+                            .m1()
+                            .variant(M1::M0)
+                            .m0()
+                            .variant(M0::BIT9)
+                            .pce()
+                            .variant(PCE::ENABLED)
+                            .ps()
+                            .variant(PS::EVEN)
+                            .ue()
+                            .enabled()
+                            .te()
+                            .enabled()
+                            .re()
+                            .enabled()
+                    });
+
+                    Ok(Smartcard { usart })
                 }
+            }
+
+            impl Smartcard<$USARTX> {
+                /// Return true if the rx register is not empty (and can be read)
+                pub fn is_rxne(&self) -> bool {
+                    self.usart.isr.read().rxne().bit_is_set()
+                }
+
+                /// Releases the USART peripheral
+                pub fn release(self) -> $USARTX {
+                    // Wait until both TXFIFO and shift register are empty
+                    while self.usart.isr.read().tc().bit_is_clear() {}
 
-                fn serial_unchecked(self,
-                                   config: impl Into<config::Config>,
-                                   prec: rec::$Rec,
-                                   clocks: &CoreClocks
-                ) -> Result<Serial<$USARTX>, config::InvalidConfig>
-                {
-                    Serial::$usartX(self, config, prec, clocks)
+                    self.usart
                 }
             }
 
-            impl serial::Read<u8> for Serial<$USARTX> {
+            impl serial::Read<u8> for Smartcard<$USARTX> {
                 type Error = Error;
 
+                /// Reads a character from the card. A character that
+                /// still has a parity error after all retries returns
+                /// `Error::Parity`
                 fn read(&mut self) -> nb::Result<u8, Error> {
                     let mut rx: Rx<$USARTX> = Rx {
                         _usart: PhantomData,
@@ -583,104 +2291,333 @@ macro_rules! usart {
                 }
             }
 
-            impl serial::Read<u8> for Rx<$USARTX> {
+            impl serial::Write<u8> for Smartcard<$USARTX> {
                 type Error = Error;
 
-                fn read(&mut self) -> nb::Result<u8, Error> {
-                    // NOTE(unsafe) atomic read with no side effects
-                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
-
-                    Err(if isr.pe().bit_is_set() {
-                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.pecf().clear() );};
-                        nb::Error::Other(Error::Parity)
-                    } else if isr.fe().bit_is_set() {
-                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.fecf().clear() );};
-                        nb::Error::Other(Error::Framing)
-                    } else if isr.nf().bit_is_set() {
-                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.ncf().clear() );};
-                        nb::Error::Other(Error::Noise)
-                    } else if isr.ore().bit_is_set() {
-                        unsafe { (*$USARTX::ptr()).icr.write(|w| w.orecf().clear() );};
-                        nb::Error::Other(Error::Overrun)
-                    } else if isr.rxne().bit_is_set() {
-                        // NOTE(read_volatile) see `write_volatile` below
-                        return Ok(unsafe {
-                            ptr::read_volatile(&(*$USARTX::ptr()).rdr as *const _ as *const _)
-                        });
+                /// Waits for transmission to the card to complete and
+                /// re-enables the receiver. Returns `Error::Nack` if a
+                /// character was NACKed by the card on every retry.
+                ///
+                /// This must be called after writing to the card and
+                /// before reading its response.
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.fe().bit_is_set() {
+                        self.usart.icr.write(|w| w.fecf().clear());
+                        self.usart.cr1.modify(|_, w| w.re().enabled());
+                        Err(nb::Error::Other(Error::Nack))
+                    } else if isr.tc().bit_is_set() {
+                        self.usart.cr1.modify(|_, w| w.re().enabled());
+                        Ok(())
                     } else {
-                        nb::Error::WouldBlock
-                    })
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Writes a character to the card. The receiver is
+                /// disabled until `flush`, so that the character is not
+                /// also received from the shared I/O line
+                fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.fe().bit_is_set() {
+                        self.usart.icr.write(|w| w.fecf().clear());
+                        Err(nb::Error::Other(Error::Nack))
+                    } else if isr.txe().bit_is_set() {
+                        self.usart.cr1.modify(|_, w| w.re().disabled());
+
+                        // NOTE(unsafe) atomic write to stateless register
+                        unsafe {
+                            self.usart.tdr.write(|w| w.tdr().bits(u16::from(byte)))
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
                 }
             }
 
-            impl Rx<$USARTX> {
-                /// Start listening for `Rxne` event
-                pub fn listen(&mut self) {
-                    // unsafe: rxneie bit accessed by Rx part only
-                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.rxneie().enabled());
+            impl serial_block::write::Default<u8> for Smartcard<$USARTX> {}
+        )+
+    }
+}
+
+/// Ring buffer of bytes, stored in a caller-provided slice
+struct RingBuffer<'a> {
+    buffer: &'a mut [u8],
+    /// Index of the oldest byte
+    start: usize,
+    /// Number of bytes held
+    len: usize,
+}
+
+impl<'a> RingBuffer<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        RingBuffer {
+            buffer,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.buffer.len()
+    }
+
+    /// Appends `byte`, returning false if the buffer is full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let end = (self.start + self.len) % self.buffer.len();
+        self.buffer[end] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the oldest byte
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buffer[self.start];
+        self.start = (self.start + 1) % self.buffer.len();
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn release(self) -> &'a mut [u8] {
+        self.buffer
+    }
+}
+
+/// Interrupt-driven serial port, which buffers received and transmitted
+/// data in caller-provided ring buffers
+///
+/// `on_interrupt` must be called from the USART interrupt. The
+/// `BufferedSerial` is typically shared with the interrupt in a
+/// `cortex_m::interrupt::Mutex`, so that the handler does not run whilst it
+/// is being read or written. Reading from an empty buffer or writing to a
+/// full buffer moves data to or from the FIFOs directly, so blocking on
+/// either still makes progress with the interrupt masked.
+///
+/// It can be split into a `BufferedTx` and a `BufferedRx`, so that the
+/// transmitter and receiver can be shared separately.
+pub struct BufferedSerial<USART> {
+    serial: Serial<USART>,
+    tx: BufferedTx<USART>,
+    rx: BufferedRx<USART>,
+}
+
+/// Transmit half of a `BufferedSerial`
+///
+/// `on_interrupt` must be called from the USART interrupt.
+pub struct BufferedTx<USART> {
+    tx: Tx<USART>,
+    buffer: RingBuffer<'static>,
+}
+
+/// Receive half of a `BufferedSerial`
+///
+/// `on_interrupt` must be called from the USART interrupt.
+///
+/// A receive error is reported by the read that follows the bytes received
+/// before it. Data lost because the receive buffer or the receive FIFO was
+/// full is reported as `Error::Overrun`. Only the first error is kept until
+/// it has been reported, and later errors in the meantime are discarded.
+pub struct BufferedRx<USART> {
+    rx: Rx<USART>,
+    buffer: RingBuffer<'static>,
+    /// Receive error to report, and the number of buffered bytes that were
+    /// received before it
+    error: Option<(Error, usize)>,
+}
+
+macro_rules! usart_buffered {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Converts into a `BufferedSerial`, which receives into
+                /// `rx_buffer` and transmits from `tx_buffer`. Enables the
+                /// FIFO threshold and idle line interrupts
+                ///
+                /// # Panics
+                ///
+                /// Panics if either buffer is empty
+                pub fn buffered(
+                    self,
+                    rx_buffer: &'static mut [u8],
+                    tx_buffer: &'static mut [u8],
+                ) -> BufferedSerial<$USARTX> {
+                    assert!(!rx_buffer.is_empty() && !tx_buffer.is_empty());
+
+                    // Interrupt when the receive FIFO is half full, and the
+                    // transmit FIFO is half empty. The transmit interrupt is
+                    // enabled whilst there is data to transmit
+                    // unsafe: 0b010 is a valid threshold
+                    self.usart.cr3.modify(|_, w| unsafe {
+                        w.rxftcfg()
+                            .bits(0b010)
+                            .rxftie()
+                            .set_bit()
+                            .txftcfg()
+                            .bits(0b010)
+                    });
+
+                    // Data below the receive FIFO threshold is collected
+                    // when the line goes idle
+                    self.usart.icr.write(|w| w.idlecf().set_bit());
+                    self.usart.cr1.modify(|_, w| w.idleie().set_bit());
+
+                    BufferedSerial {
+                        serial: self,
+                        tx: BufferedTx {
+                            tx: Tx { _usart: PhantomData },
+                            buffer: RingBuffer::new(tx_buffer),
+                        },
+                        rx: BufferedRx {
+                            rx: Rx { _usart: PhantomData },
+                            buffer: RingBuffer::new(rx_buffer),
+                            error: None,
+                        },
+                    }
+                }
+            }
+
+            impl BufferedSerial<$USARTX> {
+                /// Interrupt handler, to be called from the USART
+                /// interrupt
+                pub fn on_interrupt(&mut self) {
+                    self.rx.on_interrupt();
+                    self.tx.on_interrupt();
+                }
+
+                /// Splits into transmit and receive halves
+                pub fn split(self) -> (BufferedTx<$USARTX>, BufferedRx<$USARTX>) {
+                    (self.tx, self.rx)
                 }
 
-                /// Stop listening for `Rxne` event
-                pub fn unlisten(&mut self) {
-                    // unsafe: rxneie bit accessed by Rx part only
-                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.rxneie().disabled());
+                /// Disables the interrupts and releases the serial port
+                /// and buffers. Buffered data is discarded
+                pub fn release(
+                    self,
+                ) -> (Serial<$USARTX>, &'static mut [u8], &'static mut [u8]) {
+                    let usart = &self.serial.usart;
+                    usart.cr3.modify(|_, w| w.rxftie().clear_bit().txftie().clear_bit());
+                    usart.cr1.modify(|_, w| w.idleie().clear_bit());
+
+                    (
+                        self.serial,
+                        self.rx.buffer.release(),
+                        self.tx.buffer.release(),
+                    )
                 }
             }
 
-            impl serial::Write<u8> for Serial<$USARTX> {
-                type Error = Never;
+            impl BufferedRx<$USARTX> {
+                /// Interrupt handler, to be called from the USART
+                /// interrupt. Moves data from the receive FIFO to the
+                /// receive buffer
+                pub fn on_interrupt(&mut self) {
+                    // Clear the idle flag before draining the FIFO, so
+                    // that data received afterwards interrupts again
+                    // unsafe: atomic write to stateless register
+                    unsafe { &*$USARTX::ptr() }.icr.write(|w| w.idlecf().set_bit());
+
+                    loop {
+                        match serial::Read::<u8>::read(&mut self.rx) {
+                            Ok(byte) => {
+                                if !self.buffer.push(byte) {
+                                    self.set_error(Error::Overrun);
+                                }
+                            }
+                            Err(nb::Error::Other(error)) => self.set_error(error),
+                            Err(nb::Error::WouldBlock) => break,
+                        }
+                    }
+                }
 
-                fn flush(&mut self) -> nb::Result<(), Never> {
-                    let mut tx: Tx<$USARTX> = Tx {
-                        _usart: PhantomData,
-                    };
-                    tx.flush()
+                /// Records `error` behind the bytes already buffered,
+                /// unless an earlier error has not been reported yet
+                fn set_error(&mut self, error: Error) {
+                    if self.error.is_none() {
+                        self.error = Some((error, self.buffer.len));
+                    }
                 }
+            }
 
-                fn write(&mut self, byte: u8) -> nb::Result<(), Never> {
-                    let mut tx: Tx<$USARTX> = Tx {
-                        _usart: PhantomData,
-                    };
-                    tx.write(byte)
+            impl serial::Read<u8> for BufferedRx<$USARTX> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    if self.buffer.is_empty() {
+                        self.on_interrupt();
+                    }
+
+                    match self.error {
+                        Some((_, 0)) => {
+                            let (error, _) = self.error.take().unwrap();
+                            Err(nb::Error::Other(error))
+                        }
+                        Some((_, ref mut preceding)) => {
+                            *preceding -= 1;
+                            Ok(self.buffer.pop().unwrap())
+                        }
+                        None => self.buffer.pop().ok_or(nb::Error::WouldBlock),
+                    }
                 }
             }
 
-            impl serial_block::write::Default<u8> for Serial<$USARTX> {
-                //implement marker trait to opt-in to default blocking write implementation
+            impl BufferedTx<$USARTX> {
+                /// Interrupt handler, to be called from the USART
+                /// interrupt. Moves data from the transmit buffer to the
+                /// transmit FIFO, and disables the transmit interrupt once
+                /// the transmit buffer is empty
+                pub fn on_interrupt(&mut self) {
+                    // unsafe: txftie bit accessed by Tx part only
+                    let usart = unsafe { &*$USARTX::ptr() };
+
+                    while usart.isr.read().txe().bit_is_set() {
+                        match self.buffer.pop() {
+                            Some(byte) => {
+                                serial::Write::<u8>::write(&mut self.tx, byte).ok();
+                            }
+                            None => {
+                                usart.cr3.modify(|_, w| w.txftie().clear_bit());
+                                break;
+                            }
+                        }
+                    }
+                }
             }
 
-            impl serial::Write<u8> for Tx<$USARTX> {
-                // NOTE(Void) See section "29.7 USART interrupts"; the
-                // only possible errors during transmission are: clear
-                // to send (which is disabled in this case) errors and
-                // framing errors (which only occur in SmartCard
-                // mode); neither of these apply to our hardware
-                // configuration
+            impl serial::Write<u8> for BufferedTx<$USARTX> {
                 type Error = Never;
 
                 fn flush(&mut self) -> nb::Result<(), Never> {
-                    // NOTE(unsafe) atomic read with no side effects
-                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
-
-                    if isr.tc().bit_is_set() {
-                        Ok(())
+                    if self.buffer.is_empty() {
+                        serial::Write::<u8>::flush(&mut self.tx)
                     } else {
+                        self.on_interrupt();
                         Err(nb::Error::WouldBlock)
                     }
                 }
 
                 fn write(&mut self, byte: u8) -> nb::Result<(), Never> {
-                    // NOTE(unsafe) atomic read with no side effects
-                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+                    if self.buffer.is_full() {
+                        self.on_interrupt();
+                    }
 
-                    if isr.txe().bit_is_set() {
-                        // NOTE(unsafe) atomic write to stateless register
-                        // NOTE(write_volatile) 8-bit write that's not
-                        // possible through the svd2rust API
-                        unsafe {
-                            ptr::write_volatile(
-                                &(*$USARTX::ptr()).tdr as *const _ as *mut _, byte)
-                        }
+                    if self.buffer.push(byte) {
+                        // unsafe: txftie bit accessed by Tx part only
+                        unsafe { &*$USARTX::ptr() }
+                            .cr3
+                            .modify(|_, w| w.txftie().set_bit());
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
@@ -688,19 +2625,29 @@ macro_rules! usart {
                 }
             }
 
-            impl Tx<$USARTX> {
-                /// Start listening for `Txe` event
-                pub fn listen(&mut self) {
-                    // unsafe: txeie bit accessed by Tx part only
-                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.txeie().enabled());
+            impl serial::Read<u8> for BufferedSerial<$USARTX> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    self.rx.read()
+                }
+            }
+
+            impl serial::Write<u8> for BufferedSerial<$USARTX> {
+                type Error = Never;
+
+                fn flush(&mut self) -> nb::Result<(), Never> {
+                    self.tx.flush()
                 }
 
-                /// Stop listening for `Txe` event
-                pub fn unlisten(&mut self) {
-                    // unsafe: txeie bit accessed by Tx part only
-                    unsafe { &*$USARTX::ptr() }.cr1.modify(|_, w| w.txeie().disabled());
+                fn write(&mut self, byte: u8) -> nb::Result<(), Never> {
+                    self.tx.write(byte)
                 }
             }
+
+            impl serial_block::write::Default<u8> for BufferedTx<$USARTX> {}
+
+            impl serial_block::write::Default<u8> for BufferedSerial<$USARTX> {}
         )+
     }
 }
@@ -721,7 +2668,7 @@ macro_rules! usart16sel {
                         Val(d2ccip2r::USART16SEL_A::PLL3_Q) => clocks.pll3_q_ck(),
                         Val(d2ccip2r::USART16SEL_A::HSI_KER) => clocks.hsi_ck(),
                         Val(d2ccip2r::USART16SEL_A::CSI_KER) => clocks.csi_ck(),
-                        Val(d2ccip2r::USART16SEL_A::LSE) => unimplemented!(),
+                        Val(d2ccip2r::USART16SEL_A::LSE) => lse_ck(),
                         _ => unreachable!(),
                     }
                 }
@@ -745,7 +2692,376 @@ macro_rules! usart234578sel {
                         Val(d2ccip2r::USART234578SEL_A::PLL3_Q) => clocks.pll3_q_ck(),
                         Val(d2ccip2r::USART234578SEL_A::HSI_KER) => clocks.hsi_ck(),
                         Val(d2ccip2r::USART234578SEL_A::CSI_KER) => clocks.csi_ck(),
-                        Val(d2ccip2r::USART234578SEL_A::LSE) => unimplemented!(),
+                        Val(d2ccip2r::USART234578SEL_A::LSE) => lse_ck(),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+/// Returns the frequency of the LSE, if it is running
+///
+/// The RCC does not configure the LSE, so a 32.768kHz crystal is assumed
+fn lse_ck() -> Option<Hertz> {
+    // unsafe: read only
+    let bdcr = unsafe { (*stm32::RCC::ptr()).bdcr.read() };
+
+    if bdcr.lserdy().is_ready() {
+        Some(Hertz(32_768))
+    } else {
+        None
+    }
+}
+
+/// Divisions of the kernel clock selectable by the PRESC register
+const PRESC_DIVISIONS: [u32; 12] =
+    [1, 2, 4, 6, 8, 10, 12, 16, 32, 64, 128, 256];
+
+/// Maximum accepted baudrate error, in parts per million
+const MAX_BAUDRATE_ERROR_PPM: u32 = 20_000;
+
+/// Returns the error of the achieved baudrate, `numerator / divisor`,
+/// relative to the requested `baudrate`, in parts per million
+fn baudrate_error_ppm(baudrate: u32, numerator: u64, divisor: u64) -> u32 {
+    let requested = u64::from(baudrate) * divisor;
+    let difference =
+        cmp::max(numerator, requested) - cmp::min(numerator, requested);
+
+    cmp::min(difference * 1_000_000 / requested, u64::from(u32::MAX)) as u32
+}
+
+/// Checks that the achieved baudrate, `numerator / divisor`, lies within
+/// tolerance of the requested `baudrate`
+fn check_baudrate(
+    baudrate: u32,
+    numerator: u64,
+    divisor: u64,
+) -> Result<(), config::InvalidConfig> {
+    let error_ppm = baudrate_error_ppm(baudrate, numerator, divisor);
+
+    if error_ppm <= MAX_BAUDRATE_ERROR_PPM {
+        Ok(())
+    } else {
+        let achieved = (numerator + divisor / 2) / divisor;
+        Err(config::InvalidConfig::Baudrate {
+            achieved: Hertz(achieved as u32),
+            error_ppm,
+        })
+    }
+}
+
+/// Calculates the PRESC, OVER8 and BRR values for a USART with kernel clock
+/// `ker_ck` to run at `baudrate`.
+///
+/// The USART baudrate is ker_ck / (PRESC division * USARTDIV) with 16x
+/// oversampling, and 2 * ker_ck / (PRESC division * USARTDIV) with 8x
+/// oversampling. USARTDIV must lie in the range 16 to 0xFFFF in both
+/// cases. The smallest prescaler that brings USARTDIV into range for 16x
+/// oversampling is chosen, keeping the finest resolution. If `allow_over8`
+/// is set, USARTDIV is also calculated for 8x oversampling, and whichever
+/// oversampling gives the lower error is used.
+///
+/// With 8x oversampling BRR has no bit for USARTDIV[0], so only even values
+/// of USARTDIV can be programmed. 8x oversampling therefore reaches the same
+/// baudrates as 16x oversampling, and extends the range to twice the
+/// fastest baudrate of 16x oversampling.
+fn usart_brr(
+    ker_ck: u32,
+    baudrate: u32,
+    allow_over8: bool,
+) -> Result<(u8, bool, u16), config::InvalidConfig> {
+    // Kernel clock cycles per bit for a PRESC division
+    let cycles = |div: u32| {
+        let divisor = u64::from(div) * u64::from(baudrate);
+        (u64::from(ker_ck) + divisor / 2) / divisor
+    };
+
+    let presc = PRESC_DIVISIONS
+        .iter()
+        .position(|&div| cycles(div) <= 0xFFFF)
+        .unwrap_or(PRESC_DIVISIONS.len() - 1);
+    let div = PRESC_DIVISIONS[presc];
+
+    // USARTDIV is limited to its range, so that the errors of the closest
+    // achievable baudrates are compared. On a tie 16x oversampling is kept,
+    // as it is more tolerant to clock deviation
+    let (over8, multiplier, usartdiv) = [(false, 1), (true, 2)]
+        .iter()
+        .filter(|&&(over8, _)| allow_over8 || !over8)
+        .map(|&(over8, multiplier)| {
+            let usartdiv = cmp::max(multiplier * cycles(div), 16);
+            let max = if over8 { 0xFFFE } else { 0xFFFF };
+            (over8, multiplier, cmp::min(usartdiv, max))
+        })
+        .min_by_key(|&(_, multiplier, usartdiv)| {
+            baudrate_error_ppm(
+                baudrate,
+                multiplier * u64::from(ker_ck),
+                u64::from(div) * usartdiv,
+            )
+        })
+        .unwrap();
+    check_baudrate(
+        baudrate,
+        multiplier * u64::from(ker_ck),
+        u64::from(div) * usartdiv,
+    )?;
+
+    // With 8x oversampling BRR[2:0] holds USARTDIV[3:0] shifted right by
+    // one bit, and BRR[3] must be kept clear
+    let usartdiv = usartdiv as u16;
+    let brr = if over8 {
+        (usartdiv & !0xF) | ((usartdiv & 0xF) >> 1)
+    } else {
+        usartdiv
+    };
+
+    Ok((presc as u8, over8, brr))
+}
+
+/// Calculates the PRESC and BRR values for a LPUART with kernel clock
+/// `ker_ck` to run at `baudrate`.
+///
+/// The LPUART baudrate is 256 * ker_ck / (PRESC division * BRR), where BRR
+/// must lie in the range 0x300 to 0xFFFFF. The smallest prescaler that
+/// brings BRR into range is chosen, keeping the finest resolution.
+fn lpuart_brr(
+    ker_ck: u32,
+    baudrate: u32,
+) -> Result<(u8, u32), config::InvalidConfig> {
+    let (presc, brr) = PRESC_DIVISIONS
+        .iter()
+        .map(|&div| {
+            let divisor = u64::from(div) * u64::from(baudrate);
+            (256 * u64::from(ker_ck) + divisor / 2) / divisor
+        })
+        .enumerate()
+        .find(|&(_, brr)| brr <= 0xF_FFFF)
+        .unwrap_or((PRESC_DIVISIONS.len() - 1, 0xF_FFFF));
+
+    let brr = cmp::max(brr, 0x300);
+    check_baudrate(
+        baudrate,
+        256 * u64::from(ker_ck),
+        u64::from(PRESC_DIVISIONS[presc]) * brr,
+    )?;
+
+    Ok((presc as u8, brr as u32))
+}
+
+macro_rules! lpuart {
+    ($(
+        $USARTX:ident: ($usartX:ident, $Rec:ident, $pclkX:ident),
+    )+) => {
+        $(
+            usart_common! {
+                $USARTX: ($usartX, $Rec, ne,
+                    [], [LinBreak, ReceiverTimeout])
+            }
+
+            /// Configures a LPUART peripheral to provide serial
+            /// communication
+            impl Serial<$USARTX> {
+                /// Configures the LPUART with `config`.
+                ///
+                /// The kernel clock is selected in the RCC. The RCC does
+                /// not configure the LSE, so when the LSE is selected it
+                /// is assumed to be a 32.768kHz crystal and baudrates are
+                /// calculated from that frequency.
+                pub fn $usartX(
+                    usart: $USARTX,
+                    config: impl Into<config::Config>,
+                    prec: rec::$Rec,
+                    clocks: &CoreClocks
+                ) -> Result<Self, config::InvalidConfig>
+                {
+                    use self::config::*;
+
+                    let config = config.into();
+
+                    // Enable clock for LPUART and reset
+                    prec.enable().reset();
+
+                    // Get kernel clock
+                    let lpuart_ker_ck = match Self::kernel_clk(clocks) {
+                        Some(ker_hz) => ker_hz.0,
+                        _ => panic!("$USARTX kernel clock not running!")
+                    };
+
+                    // Calculate prescaler and baudrate divisor
+                    let (presc, brr) =
+                        lpuart_brr(lpuart_ker_ck, config.baudrate.0)?;
+
+                    // Driver enable times are counted in kernel clock
+                    // cycles. A bit time is PRESC division * BRR / 256
+                    // cycles. They are rounded up, and limited to 31
+                    // cycles
+                    let de_cycles = |time: BitTime| {
+                        let cycles_per_bit =
+                            u64::from(PRESC_DIVISIONS[presc as usize])
+                                * u64::from(brr);
+                        let product =
+                            u64::from(time.as_sixteenths()) * cycles_per_bit;
+                        product / 4096 + u64::from(product % 4096 != 0)
+                    };
+                    let deat = de_cycles(config.de_assertion_time);
+                    let dedt = de_cycles(config.de_deassertion_time);
+                    if deat > 31 || dedt > 31 {
+                        return Err(InvalidConfig::Unsupported);
+                    }
+
+                    // LIN mode and automatic baudrate detection are not
+                    // supported by the LPUART
+                    match (config.lin, &config.autobaud) {
+                        (false, AutoBaud::AutoBaudDisabled) => {},
+                        _ => return Err(InvalidConfig::Unsupported),
+                    }
+
+                    // 0.5 and 1.5 stop bits are not supported by the LPUART
+                    let stop = match config.stopbits {
+                        StopBits::STOP1 => 0b00,
+                        StopBits::STOP2 => 0b10,
+                        _ => return Err(InvalidConfig::Unsupported),
+                    };
+
+                    // unsafe: values checked above
+                    usart.presc.write(|w| unsafe { w.prescaler().bits(presc) });
+                    usart.brr.write(|w| unsafe { w.brr().bits(brr) });
+
+                    // Reset registers to disable advanced LPUART features
+                    // and DMA requests
+                    usart.cr2.reset();
+                    usart.cr3.reset();
+
+                    // Hardware flow control and RS-485 driver enable
+                    let (rts_cts, de) = match config.flowcontrol {
+                        FlowControl::FlowControlNone => (false, false),
+                        FlowControl::FlowControlRtsCts => (true, false),
+                        FlowControl::FlowControlRs485 => (false, true),
+                    };
+                    let de_active_low = match config.depolarity {
+                        DePolarity::DePolarityHigh => false,
+                        DePolarity::DePolarityLow => true,
+                    };
+                    usart.cr3.write(|w| {
+                        w.rtse()
+                            .bit(rts_cts)
+                            .ctse()
+                            .bit(rts_cts)
+                            .dem()
+                            .bit(de)
+                            .dep()
+                            .bit(de_active_low)
+                    });
+
+                    // Set stop bits, pin swap, inversion and bit order
+                    usart.cr2.write(|w| unsafe {
+                        w.stop()
+                            .bits(stop)
+                            .swap()
+                            .bit(config.swap_tx_rx)
+                            .rxinv()
+                            .bit(config.invert_rx)
+                            .txinv()
+                            .bit(config.invert_tx)
+                            .datainv()
+                            .bit(config.invert_data)
+                            .msbfirst()
+                            .bit(config.msb_first)
+                    });
+
+                    // Enable transmission and receiving
+                    // and configure frame
+                    // unsafe: DEAT and DEDT limited to 31 above
+                    usart.cr1.write(|w| unsafe {
+                        w.fifoen()
+                            .set_bit() // FIFO mode enabled
+                            .ue()
+                            .set_bit()
+                            .te()
+                            .set_bit()
+                            .re()
+                            .set_bit()
+                            .deat()
+                            .bits(deat as u8)
+                            .dedt()
+                            .bits(dedt as u8)
+                            .m1()
+                            .bit(match config.wordlength {
+                                WordLength::DataBits7 => true,
+                                _ => false,
+                            })
+                            .m0()
+                            .bit(match config.wordlength {
+                                WordLength::DataBits9 => true,
+                                _ => false,
+                            }).pce()
+                            .bit(match config.parity {
+                                Parity::ParityNone => false,
+                                _ => true,
+                            }).ps()
+                            .bit(match config.parity {
+                                Parity::ParityOdd => true,
+                                _ => false,
+                            })
+                    });
+
+                    Ok(Serial { usart })
+                }
+
+                /// Allows the LPUART to wake the system from Stop mode
+                /// when `wakeup` occurs, and enables the wakeup
+                /// interrupt.
+                ///
+                /// The kernel clock must be one that can run in Stop
+                /// mode: HSI, CSI or LSE. The corresponding EXTI line
+                /// (`LPUART1_RX`) must also be unmasked.
+                pub fn enable_stop_mode_wakeup(&mut self, wakeup: Wakeup) {
+                    let wus = match wakeup {
+                        Wakeup::StartBit => 0b10,
+                        Wakeup::Rxne => 0b11,
+                    };
+
+                    // WUS can only be written whilst the LPUART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr3.modify(|_, w| unsafe {
+                        w.wus().bits(wus).wufie().set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.uesm().set_bit().ue().set_bit());
+                }
+
+                /// Stops the LPUART from waking the system from Stop
+                /// mode, and disables the wakeup interrupt
+                pub fn disable_stop_mode_wakeup(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.uesm().clear_bit());
+                    self.usart.cr3.modify(|_, w| w.wufie().clear_bit());
+                }
+
+                /// Return true if the wakeup from Stop mode flag is set
+                pub fn is_wakeup(&self) -> bool {
+                    self.usart.isr.read().wuf().bit_is_set()
+                }
+
+                /// Clears the wakeup from Stop mode flag
+                pub fn clear_wakeup(&mut self) {
+                    self.usart.icr.write(|w| w.wucf().set_bit());
+                }
+
+                /// Returns the frequency of the current kernel clock
+                /// for LPUART1
+                fn kernel_clk(clocks: &CoreClocks) -> Option<Hertz> {
+                    // unsafe: read only
+                    let d3ccipr = unsafe { (*stm32::RCC::ptr()).d3ccipr.read() };
+
+                    match d3ccipr.lpuart1sel().variant() {
+                        Val(d3ccipr::LPUART1SEL_A::RCC_PCLK_D3) => Some(clocks.$pclkX()),
+                        Val(d3ccipr::LPUART1SEL_A::PLL2_Q) => clocks.pll2_q_ck(),
+                        Val(d3ccipr::LPUART1SEL_A::PLL3_Q) => clocks.pll3_q_ck(),
+                        Val(d3ccipr::LPUART1SEL_A::HSI_KER) => clocks.hsi_ck(),
+                        Val(d3ccipr::LPUART1SEL_A::CSI_KER) => clocks.csi_ck(),
+                        Val(d3ccipr::LPUART1SEL_A::LSE) => lse_ck(),
                         _ => unreachable!(),
                     }
                 }
@@ -766,6 +3082,32 @@ usart! {
     UART8: (uart8, Uart8, pclk1),
 }
 
+lpuart! {
+    LPUART1: (lpuart1, Lpuart1, pclk4),
+}
+
+usart_dma! {
+    USART1, USART2, USART3, USART6, UART4, UART5, UART7, UART8,
+}
+
+usart_sync! {
+    USART1: (usart1, Usart1),
+    USART2: (usart2, Usart2),
+    USART3: (usart3, Usart3),
+    USART6: (usart6, Usart6),
+}
+
+usart_smartcard! {
+    USART1: (usart1, Usart1),
+    USART2: (usart2, Usart2),
+    USART3: (usart3, Usart3),
+    USART6: (usart6, Usart6),
+}
+
+usart_buffered! {
+    USART1, USART2, USART3, UART4, UART5, USART6, UART7, UART8, LPUART1,
+}
+
 usart16sel! {
     USART1, USART6,
 }
@@ -782,3 +3124,133 @@ where
         Ok(())
     }
 }
+
+impl<USART> fmt::Write for BufferedSerial<USART>
+where
+    BufferedSerial<USART>: serial::Write<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = s.as_bytes().iter().map(|c| block!(self.write(*c))).last();
+        Ok(())
+    }
+}
+
+impl<USART> fmt::Write for BufferedTx<USART>
+where
+    BufferedTx<USART>: serial::Write<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = s.as_bytes().iter().map(|c| block!(self.write(*c))).last();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::config::InvalidConfig;
+    use super::{lpuart_brr, usart_brr, RingBuffer};
+
+    #[test]
+    fn usart_baudrate() {
+        // 16x oversampling
+        assert_eq!(
+            usart_brr(100_000_000, 115_200, true).unwrap(),
+            (0, false, 868)
+        );
+        // Prescaler for slow baudrates
+        assert_eq!(
+            usart_brr(100_000_000, 100, true).unwrap(),
+            (7, false, 62500)
+        );
+        // 8x oversampling for fast baudrates
+        assert_eq!(
+            usart_brr(100_000_000, 12_500_000, true).unwrap(),
+            (0, true, 0x10)
+        );
+        assert_eq!(
+            usart_brr(100_000_000, 10_000_000, true).unwrap(),
+            (0, true, 0x12)
+        );
+    }
+
+    #[test]
+    fn usart_baudrate_invalid() {
+        // Too fast without 8x oversampling
+        match usart_brr(100_000_000, 12_500_000, false) {
+            Err(InvalidConfig::Baudrate {
+                achieved,
+                error_ppm,
+            }) => {
+                assert_eq!(achieved.0, 6_250_000);
+                assert_eq!(error_ppm, 500_000);
+            }
+            _ => panic!(),
+        }
+        // Too fast
+        match usart_brr(100_000_000, 20_000_000, true) {
+            Err(InvalidConfig::Baudrate {
+                achieved,
+                error_ppm,
+            }) => {
+                assert_eq!(achieved.0, 12_500_000);
+                assert_eq!(error_ppm, 375_000);
+            }
+            _ => panic!(),
+        }
+        // Too inaccurate. USARTDIV of 27 with 8x oversampling would be
+        // accurate, but cannot be programmed as BRR has no bit for
+        // USARTDIV[0]
+        match usart_brr(100_000_000, 7_400_000, true) {
+            Err(InvalidConfig::Baudrate {
+                achieved,
+                error_ppm,
+            }) => {
+                assert_eq!(achieved.0, 7_142_857);
+                assert_eq!(error_ppm, 34_749);
+            }
+            _ => panic!(),
+        }
+        // Too slow
+        match usart_brr(100_000_000, 1, true) {
+            Err(InvalidConfig::Baudrate { achieved, .. }) => {
+                assert_eq!(achieved.0, 6);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn lpuart_baudrate() {
+        assert_eq!(lpuart_brr(32_768, 9600).unwrap(), (0, 874));
+        assert_eq!(lpuart_brr(100_000_000, 9600).unwrap(), (2, 666_667));
+
+        match lpuart_brr(32_768, 19_200) {
+            Err(InvalidConfig::Baudrate { achieved, .. }) => {
+                assert_eq!(achieved.0, 10_923);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut storage = [0; 3];
+        let mut buffer = RingBuffer::new(&mut storage);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+
+        assert!(buffer.push(1));
+        assert!(buffer.push(2));
+        assert!(buffer.push(3));
+        assert!(buffer.is_full());
+        assert!(!buffer.push(4));
+
+        assert_eq!(buffer.pop(), Some(1));
+        // Wraps around the end of the storage
+        assert!(buffer.push(4));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), Some(3));
+        assert_eq!(buffer.pop(), Some(4));
+        assert!(buffer.is_empty());
+    }
+}
@@ -52,8 +52,38 @@
 //! - SPI4, SPI5: __APB__
 //! - SPI6: __PCLK4__
 //!
+//! ## DMA
+//!
+//! Long transactions can be performed by DMA with `write_dma` and
+//! `transfer_dma`. The transaction size is programmed in TSIZE, so the
+//! SPI ends the transaction by itself and sets the EOT flag.
+//!
+//! ```
+//! let streams = dma::dma::StreamsTuple::new(dp.DMA1, ccdr.peripheral.DMA1);
+//! let config = dma::dma::DmaConfig::default().memory_increment(true);
+//!
+//! // `BUFFER` is a `&'static [u8]`, not located in DTCM
+//! let transaction = spi.write_dma(streams.0, BUFFER, config);
+//! while !transaction.is_eot() {}
+//! let (spi, stream, buffer) = transaction.free();
+//! ```
+//!
+//! Transmit-only transactions operate the SPI as a simplex transmitter, so
+//! no receive buffer is needed.
+//!
+//! SPI1 - SPI5 are served by DMA1 / DMA2 streams through DMAMUX1. SPI6 is
+//! in the D3 domain and is only served by BDMA channels through DMAMUX2, so
+//! its buffers must be located in SRAM4. Passing a stream of the wrong
+//! controller is a compile error.
+//!
 //! [embedded_hal]: https://docs.rs/embedded-hal/0.2.3/embedded_hal/spi/index.html
 
+use crate::dma::{
+    bdma, dma,
+    traits::{MemoryAccess, Stream, TargetAddress},
+    MemoryToPeripheral, PeripheralToMemory, StaticReadBuffer,
+    StaticWriteBuffer, Transfer,
+};
 use crate::hal;
 pub use crate::hal::spi::{
     Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3,
@@ -67,6 +97,7 @@ use core::ptr;
 use nb;
 use stm32h7::Variant::Val;
 
+use crate::stm32::{DMAMUX1, DMAMUX2};
 use crate::stm32::{SPI1, SPI2, SPI3, SPI4, SPI5, SPI6};
 
 use crate::gpio::gpioa::{PA12, PA5, PA6, PA7, PA9};
@@ -344,6 +375,41 @@ pub struct Spi<SPI, ED, WORD = u8> {
     _ed: PhantomData<ED>,
 }
 
+/// DMA target for the transmit data register of an SPI peripheral
+pub struct SpiTx<SPI, WORD> {
+    _spi: PhantomData<SPI>,
+    _word: PhantomData<WORD>,
+}
+
+/// DMA target for the receive data register of an SPI peripheral
+pub struct SpiRx<SPI, WORD> {
+    _spi: PhantomData<SPI>,
+    _word: PhantomData<WORD>,
+}
+
+/// A transmit-only SPI transaction using DMA. Returned by `write_dma`
+pub struct SpiDmaWrite<SPI, WORD, STREAM, BUF>
+where
+    STREAM: Stream,
+    SpiTx<SPI, WORD>: TargetAddress<MemoryToPeripheral, Mux = STREAM::Mux>,
+{
+    spi: Spi<SPI, Enabled, WORD>,
+    tx: Transfer<STREAM, SpiTx<SPI, WORD>, MemoryToPeripheral, BUF>,
+}
+
+/// A full-duplex SPI transaction using DMA. Returned by `transfer_dma`
+pub struct SpiDmaTransfer<SPI, WORD, TXSTREAM, RXSTREAM, TXBUF, RXBUF>
+where
+    TXSTREAM: Stream,
+    RXSTREAM: Stream,
+    SpiTx<SPI, WORD>: TargetAddress<MemoryToPeripheral, Mux = TXSTREAM::Mux>,
+    SpiRx<SPI, WORD>: TargetAddress<PeripheralToMemory, Mux = RXSTREAM::Mux>,
+{
+    spi: Spi<SPI, Enabled, WORD>,
+    tx: Transfer<TXSTREAM, SpiTx<SPI, WORD>, MemoryToPeripheral, TXBUF>,
+    rx: Transfer<RXSTREAM, SpiRx<SPI, WORD>, PeripheralToMemory, RXBUF>,
+}
+
 pub trait SpiExt<SPI, WORD>: Sized {
     type Rec: ResetEnable;
 
@@ -698,6 +764,246 @@ macro_rules! spi {
 	}
 }
 
+macro_rules! spi_dma {
+    ($($SPIX:ident: $Mux:ident ($rx_req:path, $tx_req:path) => ($($TY:ident),+),)+) => {
+        $(
+            // For each $TY
+            $(
+                unsafe impl TargetAddress<MemoryToPeripheral> for SpiTx<$SPIX, $TY> {
+                    type MemSize = $TY;
+                    type Mux = $Mux;
+
+                    const REQUEST_LINE: Option<u8> = Some($tx_req as u8);
+
+                    fn address(&self) -> usize {
+                        // unsafe: only the address is taken
+                        unsafe { &(*$SPIX::ptr()).txdr as *const _ as usize }
+                    }
+                }
+
+                unsafe impl TargetAddress<PeripheralToMemory> for SpiRx<$SPIX, $TY> {
+                    type MemSize = $TY;
+                    type Mux = $Mux;
+
+                    const REQUEST_LINE: Option<u8> = Some($rx_req as u8);
+
+                    fn address(&self) -> usize {
+                        // unsafe: only the address is taken
+                        unsafe { &(*$SPIX::ptr()).rxdr as *const _ as usize }
+                    }
+                }
+
+                impl Spi<$SPIX, Enabled, $TY> {
+                    /// Prepares a transaction of `len` words. The SPI is
+                    /// left disabled, since TSIZE and COMM can only be
+                    /// written while SPE = 0
+                    fn dma_setup(&mut self, len: usize, transmit_only: bool) {
+                        assert!(len > 0 && len <= 65535,
+                                "Buffer length must be 1 - 65535 words");
+
+                        self.spi.cr1.modify(|_, w| w.spe().disabled());
+                        self.spi.cfg2.modify(|_, w| {
+                            if transmit_only {
+                                w.comm().transmitter()
+                            } else {
+                                w.comm().full_duplex()
+                            }
+                        });
+                        self.spi.cr2.modify(|_, w| w.tsize().bits(len as u16));
+                        self.spi.ifcr.write(|w| w.eotc().clear().txtfc().clear());
+                    }
+
+                    /// Enables the SPI and starts the transaction
+                    fn dma_start(&mut self) {
+                        self.spi.cr1.modify(|_, w| w.spe().enabled());
+                        self.spi.cr1.modify(|_, w| w.cstart().started());
+                    }
+
+                    /// Ends a DMA transaction, and returns the SPI to
+                    /// full-duplex operation without DMA
+                    fn dma_end(&mut self) {
+                        // Suspend the transaction if it has not yet ended
+                        if self.spi.sr.read().eot().is_not_completed() {
+                            self.spi.cr1.modify(|_, w| w.csusp().requested());
+                            loop {
+                                let sr = self.spi.sr.read();
+                                if sr.eot().is_completed() || sr.susp().is_suspended() {
+                                    break;
+                                }
+                            }
+                        }
+
+                        self.spi.cr1.modify(|_, w| w.spe().disabled());
+                        self.spi.cfg1.modify(|_, w| {
+                            w.txdmaen().disabled().rxdmaen().disabled()
+                        });
+                        self.spi.cfg2.modify(|_, w| w.comm().full_duplex());
+                        self.spi.cr2.modify(|_, w| w.tsize().bits(0));
+                        self.spi.ifcr.write(|w| {
+                            w.eotc().clear().txtfc().clear().suspc().clear()
+                        });
+                        self.spi.cr1.modify(|_, w| w.spe().enabled());
+                    }
+
+                    /// Transmits `buf` using the DMA `stream`. The
+                    /// transaction is started before this method returns.
+                    ///
+                    /// The SPI operates as a simplex transmitter for the
+                    /// duration of the transaction, so no receive buffer
+                    /// or stream is required.
+                    ///
+                    /// # Panics
+                    ///
+                    /// Panics if the buffer is empty or longer than 65535
+                    /// words
+                    pub fn write_dma<STREAM, BUF>(
+                        mut self,
+                        stream: STREAM,
+                        buf: BUF,
+                        config: STREAM::Config,
+                    ) -> SpiDmaWrite<$SPIX, $TY, STREAM, BUF>
+                    where
+                        STREAM: Stream<Mux = $Mux> + MemoryAccess<BUF>,
+                        BUF: StaticReadBuffer<Word = $TY>,
+                    {
+                        let (_, len) = buf.static_read_buffer();
+                        self.dma_setup(len, true);
+
+                        let target = SpiTx { _spi: PhantomData, _word: PhantomData };
+                        let mut tx = Transfer::<_, _, MemoryToPeripheral, _>::init(
+                            stream, target, buf, config,
+                        );
+
+                        // TXDMAEN is set after the stream is enabled. See
+                        // RM0433 Rev 7 Section 50.4.14
+                        let spi = &self.spi;
+                        tx.start(|_| spi.cfg1.modify(|_, w| w.txdmaen().enabled()));
+                        self.dma_start();
+
+                        SpiDmaWrite { spi: self, tx }
+                    }
+
+                    /// Transmits `tx_buf` and receives into `rx_buf`
+                    /// simultaneously using the DMA streams `tx_stream`
+                    /// and `rx_stream`. The transaction is started before
+                    /// this method returns.
+                    ///
+                    /// # Panics
+                    ///
+                    /// Panics if the buffers have different lengths, or
+                    /// if they are empty or longer than 65535 words
+                    pub fn transfer_dma<TXSTREAM, RXSTREAM, TXBUF, RXBUF>(
+                        mut self,
+                        tx_stream: TXSTREAM,
+                        rx_stream: RXSTREAM,
+                        tx_buf: TXBUF,
+                        mut rx_buf: RXBUF,
+                        config: TXSTREAM::Config,
+                    ) -> SpiDmaTransfer<$SPIX, $TY, TXSTREAM, RXSTREAM, TXBUF, RXBUF>
+                    where
+                        TXSTREAM: Stream<Mux = $Mux> + MemoryAccess<TXBUF>,
+                        TXSTREAM::Config: Clone,
+                        RXSTREAM: Stream<Config = TXSTREAM::Config, Mux = $Mux>
+                            + MemoryAccess<RXBUF>,
+                        TXBUF: StaticReadBuffer<Word = $TY>,
+                        RXBUF: StaticWriteBuffer<Word = $TY>,
+                    {
+                        let (_, tx_len) = tx_buf.static_read_buffer();
+                        let (_, rx_len) = rx_buf.static_write_buffer();
+                        assert_eq!(tx_len, rx_len, "Buffer lengths must be equal");
+                        self.dma_setup(tx_len, false);
+
+                        // RXDMAEN is set before the streams are enabled,
+                        // and TXDMAEN after. See RM0433 Rev 7 Section
+                        // 50.4.14
+                        self.spi.cfg1.modify(|_, w| w.rxdmaen().enabled());
+
+                        let target = SpiRx { _spi: PhantomData, _word: PhantomData };
+                        let mut rx = Transfer::<_, _, PeripheralToMemory, _>::init(
+                            rx_stream, target, rx_buf, config.clone(),
+                        );
+                        rx.start(|_| {});
+
+                        let target = SpiTx { _spi: PhantomData, _word: PhantomData };
+                        let mut tx = Transfer::<_, _, MemoryToPeripheral, _>::init(
+                            tx_stream, target, tx_buf, config,
+                        );
+                        let spi = &self.spi;
+                        tx.start(|_| spi.cfg1.modify(|_, w| w.txdmaen().enabled()));
+                        self.dma_start();
+
+                        SpiDmaTransfer { spi: self, tx, rx }
+                    }
+                }
+
+                impl<STREAM, BUF> SpiDmaWrite<$SPIX, $TY, STREAM, BUF>
+                where
+                    STREAM: Stream<Mux = $Mux>,
+                {
+                    /// Return `true` if the EOT flag is set, i.e. all
+                    /// words have been transmitted
+                    pub fn is_eot(&self) -> bool {
+                        self.spi.spi.sr.read().eot().is_completed()
+                    }
+
+                    /// Returns true if the DMA transfer error flag is set
+                    pub fn get_transfer_error_flag(&self) -> bool {
+                        self.tx.get_transfer_error_flag()
+                    }
+
+                    /// Ends the transaction, and returns the SPI, the DMA
+                    /// stream and the buffer. If the transaction has not
+                    /// yet ended, it is suspended.
+                    pub fn free(self) -> (Spi<$SPIX, Enabled, $TY>, STREAM, BUF) {
+                        let SpiDmaWrite { mut spi, tx } = self;
+                        let (stream, _, buf) = tx.free();
+                        spi.dma_end();
+
+                        (spi, stream, buf)
+                    }
+                }
+
+                impl<TXSTREAM, RXSTREAM, TXBUF, RXBUF>
+                    SpiDmaTransfer<$SPIX, $TY, TXSTREAM, RXSTREAM, TXBUF, RXBUF>
+                where
+                    TXSTREAM: Stream<Mux = $Mux>,
+                    RXSTREAM: Stream<Mux = $Mux>,
+                {
+                    /// Return `true` if the EOT flag is set, i.e. all
+                    /// words have been transmitted and received
+                    pub fn is_eot(&self) -> bool {
+                        self.spi.spi.sr.read().eot().is_completed()
+                    }
+
+                    /// Returns true if the transfer error flag of either
+                    /// DMA stream is set
+                    pub fn get_transfer_error_flag(&self) -> bool {
+                        self.tx.get_transfer_error_flag()
+                            || self.rx.get_transfer_error_flag()
+                    }
+
+                    /// Ends the transaction, and returns the SPI, the DMA
+                    /// streams and the buffers. If the transaction has not
+                    /// yet ended, it is suspended.
+                    ///
+                    /// The receive buffer is only complete if `is_eot`
+                    /// returned `true`.
+                    pub fn free(
+                        self,
+                    ) -> (Spi<$SPIX, Enabled, $TY>, TXSTREAM, RXSTREAM, TXBUF, RXBUF) {
+                        let SpiDmaTransfer { mut spi, tx, rx } = self;
+                        let (tx_stream, _, tx_buf) = tx.free();
+                        spi.dma_end();
+                        let (rx_stream, _, rx_buf) = rx.free();
+
+                        (spi, tx_stream, rx_stream, tx_buf, rx_buf)
+                    }
+                }
+            )+
+        )+
+    }
+}
+
 macro_rules! spi123sel {
 	($($SPIX:ident,)+) => {
 	    $(
@@ -777,6 +1083,15 @@ spi! {
     SPI6: (spi6, Spi6, pclk2) => (u8, u16),
 }
 
+spi_dma! {
+    SPI1: DMAMUX1 (dma::DMAReq::SPI1_RX_DMA, dma::DMAReq::SPI1_TX_DMA) => (u8, u16),
+    SPI2: DMAMUX1 (dma::DMAReq::SPI2_RX_DMA, dma::DMAReq::SPI2_TX_DMA) => (u8, u16),
+    SPI3: DMAMUX1 (dma::DMAReq::SPI3_RX_DMA, dma::DMAReq::SPI3_TX_DMA) => (u8, u16),
+    SPI4: DMAMUX1 (dma::DMAReq::SPI4_RX_DMA, dma::DMAReq::SPI4_TX_DMA) => (u8, u16),
+    SPI5: DMAMUX1 (dma::DMAReq::SPI5_RX_DMA, dma::DMAReq::SPI5_TX_DMA) => (u8, u16),
+    SPI6: DMAMUX2 (bdma::DMAReq::SPI6_RX_DMA, bdma::DMAReq::SPI6_TX_DMA) => (u8, u16),
+}
+
 spi123sel! {
     SPI1, SPI2, SPI3,
 }